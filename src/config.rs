@@ -1,6 +1,9 @@
+use crate::extensions::StringExt;
+use crate::modules::HASS_MAX_BRIGHTNESS;
 use crate::options::CliOptions;
 use directories_next::ProjectDirs;
-use serde::Deserialize;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
@@ -12,22 +15,40 @@ pub struct Config {
     pub mqtt: MqttConfig,
     pub hass: HomeAssistantConfig,
     #[serde(default)]
+    pub discovery: DiscoveryProtocol,
+    #[serde(default)]
     pub modules: Modules,
 }
 
+/// Convention used to describe the device and its state to the broker
+#[derive(Default, Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiscoveryProtocol {
+    #[default]
+    HomeAssistant,
+    Homie,
+}
+
 #[derive(Default, Debug, Clone, Deserialize)]
 pub struct Modules {
-    #[serde(default)]
-    pub backlight: Option<BacklightProvider>,
+    /// Either a single backlight or a list of named backlight devices
+    #[serde(default, deserialize_with = "deserialize_backlights")]
+    pub backlight: Vec<BacklightConfig>,
     #[serde(default)]
     pub idle: Option<IdleModuleConfig>,
-    #[serde(default)]
-    // TODO: add configuration options for icon and app name
-    pub notifications: Option<bool>,
+    /// Either `true` to enable notifications with the defaults or the notification options
+    #[serde(default, deserialize_with = "deserialize_notifications")]
+    pub notifications: Option<NotificationsConfig>,
     #[serde(default)]
     pub custom_commands: Vec<CustomCommandConfig>,
     #[serde(default)]
     pub sensors: SensorsConfig,
+    #[serde(default)]
+    pub session_lock: Option<SessionLockConfig>,
+    #[serde(default)]
+    pub screensaver_inhibit: Option<ScreenSaverInhibitConfig>,
+    #[serde(default, deserialize_with = "deserialize_night_light")]
+    pub night_light: Option<NightLightConfig>,
 }
 
 #[derive(Default, Debug, Clone, Deserialize, PartialEq, Eq)]
@@ -55,14 +76,303 @@ fn sensor_poll_rate() -> Duration {
     Duration::from_secs(1)
 }
 
-#[derive(Debug, Clone, Deserialize, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 pub struct IdleModuleConfig {
+    /// Duration until the device is reported as unoccupied, shorthand for a single `idle` level
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    #[serde(default = "default_poll_rate", with = "humantime_serde")]
+    pub poll_rate: Duration,
+    /// Named idle levels, the device is `active` until the first level is reached
+    #[serde(default)]
+    pub levels: Vec<IdleLevelConfig>,
+    /// Level from which on the device is reported as unoccupied, defaults to the last level
+    #[serde(default)]
+    pub occupancy_level: Option<String>,
+    /// Source of the idle time
+    #[serde(default)]
+    pub backend: IdleBackend,
+    /// logind session to watch when using the `logind` backend, defaults to the session of desktop2mqtt
+    #[serde(default)]
+    pub session: Option<String>,
+    #[serde(default)]
+    pub bus: DbusBus,
+    /// Keeps the device occupied while a MPRIS media player on the session bus is playing
+    #[serde(default)]
+    pub media_playback: bool,
+    /// Keeps the device occupied while an application holds a logind idle inhibitor
+    #[serde(default)]
+    pub idle_inhibitors: bool,
+}
+
+#[derive(Default, Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdleBackend {
+    /// Polls the idle time of the X11/Wayland input devices
+    #[default]
+    Polling,
+    /// Listens for changes of the IdleHint reported by the desktop to logind
+    Logind,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct IdleLevelConfig {
+    pub name: String,
     #[serde(with = "humantime_serde")]
-    pub timeout: Duration,
+    pub after: Duration,
+}
+
+pub const ACTIVE_IDLE_LEVEL: &str = "active";
+
+impl IdleModuleConfig {
+    /// Configured idle levels ordered by their duration, starting with the `active` level.
+    pub fn levels(&self) -> anyhow::Result<Vec<IdleLevelConfig>> {
+        let mut levels = self.levels.clone();
+        if let Some(timeout) = self.timeout {
+            levels.push(IdleLevelConfig {
+                name: "idle".to_string(),
+                after: timeout,
+            });
+        }
+        if levels.is_empty() {
+            anyhow::bail!("The idle module requires either a timeout or a list of levels");
+        }
+        levels.sort_by_key(|level| level.after);
+        if levels[0].after > Duration::from_secs(0) {
+            levels.insert(
+                0,
+                IdleLevelConfig {
+                    name: ACTIVE_IDLE_LEVEL.to_string(),
+                    after: Duration::from_secs(0),
+                },
+            );
+        }
+
+        Ok(levels)
+    }
+
+    /// Idle duration from which on the device is reported as unoccupied.
+    pub fn occupancy_timeout(&self) -> anyhow::Result<Duration> {
+        let levels = self.levels()?;
+        let level = match &self.occupancy_level {
+            Some(name) => levels
+                .iter()
+                .find(|level| &level.name == name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown occupancy level {}", name))?,
+            None => levels.last().unwrap(),
+        };
+
+        Ok(level.after)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct SessionLockConfig {
+    /// logind session id, defaults to the session of this process
+    #[serde(default)]
+    pub session: Option<String>,
+    /// Bus logind is reached at, `session` allows running against a mock logind service
+    #[serde(default)]
+    pub bus: DbusBus,
     #[serde(default = "default_poll_rate", with = "humantime_serde")]
     pub poll_rate: Duration,
 }
 
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct ScreenSaverInhibitConfig {
+    /// Reason shown by the screen saver for the inhibit
+    #[serde(default = "default_inhibit_reason")]
+    pub reason: String,
+    #[serde(default = "session_bus")]
+    pub bus: DbusBus,
+}
+
+fn default_inhibit_reason() -> String {
+    "Requested by Home Assistant".to_string()
+}
+
+fn session_bus() -> DbusBus {
+    DbusBus::Session
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct NightLightConfig {
+    #[serde(default)]
+    pub backend: NightLightBackend,
+    /// Colour temperature in Kelvin applied when turned on without a temperature
+    #[serde(default = "default_night_light_temperature")]
+    pub temperature: u32,
+    /// Warmest colour temperature in Kelvin
+    #[serde(default = "default_night_light_min_temperature")]
+    pub min_temperature: u32,
+    /// Coldest colour temperature in Kelvin
+    #[serde(default = "default_night_light_max_temperature")]
+    pub max_temperature: u32,
+}
+
+/// Tool used to apply the colour temperature
+#[derive(Default, Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NightLightBackend {
+    /// Runs `gammastep` in one-shot mode
+    #[default]
+    Gammastep,
+    /// Runs `redshift` in one-shot mode
+    Redshift,
+    /// Keeps `wlsunset` running while turned on
+    Wlsunset,
+}
+
+fn deserialize_night_light<'de, D>(deserializer: D) -> Result<Option<NightLightConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let config = Option::<NightLightConfig>::deserialize(deserializer)?;
+    if let Some(config) = &config {
+        if config.min_temperature > config.max_temperature {
+            return Err(D::Error::custom(format!(
+                "The night light min_temperature {} is above its max_temperature {}",
+                config.min_temperature, config.max_temperature
+            )));
+        }
+    }
+
+    Ok(config)
+}
+
+fn default_night_light_temperature() -> u32 {
+    4500
+}
+
+fn default_night_light_min_temperature() -> u32 {
+    1000
+}
+
+fn default_night_light_max_temperature() -> u32 {
+    6500
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct NotificationsConfig {
+    /// Application name used when the notification doesn't provide one
+    #[serde(default = "default_notification_app_name")]
+    pub app_name: String,
+    /// Icon used when the notification doesn't provide one
+    #[serde(default)]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub timeout: Option<NotificationTimeout>,
+    #[serde(default)]
+    pub urgency: Option<NotificationUrgency>,
+    /// Hints notifications may set, defaults to all hints
+    #[serde(default)]
+    pub allowed_hints: Option<Vec<NotificationHint>>,
+    /// Topic to receive notifications on, defaults to `desktop2mqtt/<entity_id>/notify`
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// Shows a single summary instead of every notification queued while do not disturb was on
+    #[serde(default)]
+    pub do_not_disturb_summary: bool,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        NotificationsConfig {
+            app_name: default_notification_app_name(),
+            icon: None,
+            timeout: None,
+            urgency: None,
+            allowed_hints: None,
+            topic: None,
+            do_not_disturb_summary: false,
+        }
+    }
+}
+
+impl NotificationsConfig {
+    pub fn is_hint_allowed(&self, hint: NotificationHint) -> bool {
+        match self.allowed_hints.as_ref() {
+            Some(hints) => hints.contains(&hint),
+            None => true,
+        }
+    }
+
+    /// Urgency of a notification after applying the allowed hints and the default urgency.
+    pub fn urgency(&self, urgency: Option<NotificationUrgency>) -> Option<NotificationUrgency> {
+        urgency
+            .filter(|_| self.is_hint_allowed(NotificationHint::Urgency))
+            .or(self.urgency)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationUrgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+/// Timeout in milliseconds or one of `never` and `default`
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum NotificationTimeout {
+    Milliseconds(u32),
+    Named(NamedNotificationTimeout),
+}
+
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NamedNotificationTimeout {
+    Never,
+    Default,
+}
+
+/// Notification fields passed to the notification server as hints
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationHint {
+    Urgency,
+    Category,
+    SoundName,
+    Image,
+}
+
+fn default_notification_app_name() -> String {
+    "desktop2mqtt".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NotificationsConfigFormat {
+    Disabled,
+    Enabled(bool),
+    Config(NotificationsConfig),
+}
+
+fn deserialize_notifications<'de, D>(
+    deserializer: D,
+) -> Result<Option<NotificationsConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let config = match NotificationsConfigFormat::deserialize(deserializer)? {
+        NotificationsConfigFormat::Enabled(true) => Some(NotificationsConfig::default()),
+        NotificationsConfigFormat::Disabled | NotificationsConfigFormat::Enabled(false) => None,
+        NotificationsConfigFormat::Config(config) => Some(config),
+    };
+
+    Ok(config)
+}
+
+#[derive(Default, Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DbusBus {
+    #[default]
+    System,
+    Session,
+}
+
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 pub struct CustomCommandConfig {
     pub name: String,
@@ -89,6 +399,22 @@ pub struct MqttConfig {
 pub struct HomeAssistantConfig {
     pub entity_id: String,
     pub name: String,
+    /// Publish discovery messages with abbreviated keys and a `~` base topic
+    #[serde(default)]
+    pub abbreviated_discovery: bool,
+    #[serde(default)]
+    pub discovery_mode: DiscoveryMode,
+}
+
+/// Layout of the Home Assistant discovery messages
+#[derive(Default, Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiscoveryMode {
+    /// One retained config per entity
+    #[default]
+    Entity,
+    /// A single retained config describing the device with all of its entities
+    Device,
 }
 
 #[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
@@ -96,6 +422,175 @@ pub struct HomeAssistantConfig {
 pub enum BacklightProvider {
     RaspberryPi,
     Stub,
+    Sysfs,
+    Command,
+}
+
+/// Backlight provider with its options, can also be given as just the name of the provider
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct BacklightConfig {
+    /// Name of the light entity, has to be unique for multiple devices
+    #[serde(default = "default_backlight_name")]
+    pub name: String,
+    pub provider: BacklightProvider,
+    /// Device in the sysfs root, defaults to the first raw, platform or firmware device found
+    #[serde(default)]
+    pub device: Option<String>,
+    /// Directory containing the backlight devices
+    #[serde(default = "default_sysfs_root")]
+    pub sysfs_root: PathBuf,
+    /// Lowest brightness (0-255) which will be applied to the backlight
+    #[serde(default)]
+    pub min_brightness: Option<u32>,
+    /// Shell commands used by the `command` provider
+    #[serde(default)]
+    pub commands: Option<BacklightCommandsConfig>,
+    /// Interval to check for brightness changes made on the machine itself
+    #[serde(default = "default_poll_rate", with = "humantime_serde")]
+    pub poll_rate: Duration,
+    /// Local rule switching the backlight while the desktop is idle
+    #[serde(default)]
+    pub on_idle: Option<BacklightIdleConfig>,
+}
+
+impl From<BacklightProvider> for BacklightConfig {
+    fn from(provider: BacklightProvider) -> Self {
+        BacklightConfig {
+            name: default_backlight_name(),
+            provider,
+            device: None,
+            sysfs_root: default_sysfs_root(),
+            min_brightness: None,
+            commands: None,
+            poll_rate: default_poll_rate(),
+            on_idle: None,
+        }
+    }
+}
+
+/// Turns off or dims the backlight once an idle level is reached and restores it on activity.
+///
+/// Runs within desktop2mqtt, so it keeps working while Home Assistant or the broker are unavailable.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct BacklightIdleConfig {
+    /// Idle level from which on the rule applies
+    pub level: String,
+    #[serde(default)]
+    pub action: BacklightIdleAction,
+    /// Brightness (0-255) to dim to
+    #[serde(default = "default_dim_brightness")]
+    pub brightness: u32,
+}
+
+#[derive(Default, Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BacklightIdleAction {
+    #[default]
+    Off,
+    Dim,
+}
+
+fn default_dim_brightness() -> u32 {
+    25
+}
+
+/// Shell commands controlling the backlight, run with `sh -c`.
+///
+/// `{power}` is replaced with `on` or `off` and `{brightness}` with the device brightness.
+/// Without a get command the last value set is reported.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct BacklightCommandsConfig {
+    /// Prints `on`/`off`, `1`/`0` or `true`/`false`
+    pub get_power: Option<String>,
+    pub set_power: Option<String>,
+    /// Prints the device brightness
+    pub get_brightness: Option<String>,
+    pub set_brightness: Option<String>,
+    /// Brightness of the device at full brightness
+    #[serde(default = "default_command_max_brightness")]
+    pub max_brightness: u32,
+}
+
+fn default_command_max_brightness() -> u32 {
+    100
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BacklightConfigFormat {
+    Provider(BacklightProvider),
+    Config(Box<BacklightConfig>),
+}
+
+impl From<BacklightConfigFormat> for BacklightConfig {
+    fn from(format: BacklightConfigFormat) -> Self {
+        match format {
+            BacklightConfigFormat::Provider(provider) => provider.into(),
+            BacklightConfigFormat::Config(config) => *config,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BacklightsConfigFormat {
+    Single(BacklightConfigFormat),
+    Devices(Vec<BacklightConfigFormat>),
+}
+
+fn deserialize_backlights<'de, D>(deserializer: D) -> Result<Vec<BacklightConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let format = BacklightsConfigFormat::deserialize(deserializer)?;
+    let backlights: Vec<BacklightConfig> = match format {
+        BacklightsConfigFormat::Single(backlight) => vec![backlight.into()],
+        BacklightsConfigFormat::Devices(backlights) => {
+            backlights.into_iter().map(From::from).collect()
+        }
+    };
+    for (i, backlight) in backlights.iter().enumerate() {
+        if backlight.provider == BacklightProvider::Command && backlight.commands.is_none() {
+            return Err(D::Error::custom(format!(
+                "The command backlight provider of {} requires commands",
+                backlight.name
+            )));
+        }
+        if let Some(min_brightness) = backlight.min_brightness {
+            if min_brightness > HASS_MAX_BRIGHTNESS {
+                return Err(D::Error::custom(format!(
+                    "The min_brightness of {} has to be between 0 and {}",
+                    backlight.name, HASS_MAX_BRIGHTNESS
+                )));
+            }
+        }
+        let slug = backlight.name.to_slug();
+        if !slug.chars().any(|c| c.is_ascii_alphanumeric()) {
+            return Err(D::Error::custom(format!(
+                "The backlight name {} requires at least one letter or digit",
+                backlight.name
+            )));
+        }
+        if backlights[..i]
+            .iter()
+            .any(|other| other.name.to_slug() == slug)
+        {
+            return Err(D::Error::custom(format!(
+                "Duplicate backlight name {}",
+                backlight.name
+            )));
+        }
+    }
+
+    Ok(backlights)
+}
+
+fn default_backlight_name() -> String {
+    "Backlight".to_string()
+}
+
+fn default_sysfs_root() -> PathBuf {
+    PathBuf::from("/sys/class/backlight")
 }
 
 fn default_poll_rate() -> Duration {
@@ -123,6 +618,24 @@ fn get_config_file_path(options: &CliOptions) -> PathBuf {
     }
 }
 
+/// File the last known state is persisted to between restarts.
+pub(crate) fn get_state_file_path() -> Option<PathBuf> {
+    ProjectDirs::from("me", "maxjoehnk", "desktop2mqtt")
+        .map(|project_dirs| project_dirs.cache_dir().join("state.json"))
+}
+
+/// Directory decoded notification images are written to, only accessible by the current user.
+///
+/// Prefers the runtime dir, which is cleared on logout, over the cache dir.
+pub(crate) fn get_image_dir() -> Option<PathBuf> {
+    ProjectDirs::from("me", "maxjoehnk", "desktop2mqtt").map(|project_dirs| {
+        project_dirs
+            .runtime_dir()
+            .unwrap_or_else(|| project_dirs.cache_dir())
+            .join("images")
+    })
+}
+
 fn get_user_dir_path() -> Option<PathBuf> {
     if let Some(project_dirs) = ProjectDirs::from("me", "maxjoehnk", "desktop2mqtt") {
         let config_dir = project_dirs.config_dir();
@@ -0,0 +1,130 @@
+use serde_json::{Map, Value};
+
+const BASE_TOPIC: &str = "~";
+
+/// Long-form discovery keys and their abbreviated form as understood by Home Assistant.
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("availability", "avty"),
+    ("availability_mode", "avty_mode"),
+    ("availability_topic", "avty_t"),
+    ("brightness_scale", "bri_scl"),
+    ("color_mode", "clrm"),
+    ("command_template", "cmd_tpl"),
+    ("command_topic", "cmd_t"),
+    ("components", "cmps"),
+    ("device", "dev"),
+    ("device_class", "dev_cla"),
+    ("entity_category", "ent_cat"),
+    ("event_types", "evt_typ"),
+    ("expire_after", "exp_aft"),
+    ("icon", "ic"),
+    ("json_attributes_topic", "json_attr_t"),
+    ("max_mireds", "max_mirs"),
+    ("min_mireds", "min_mirs"),
+    ("options", "ops"),
+    ("origin", "o"),
+    ("payload_available", "pl_avail"),
+    ("payload_lock", "pl_lock"),
+    ("payload_not_available", "pl_not_avail"),
+    ("payload_off", "pl_off"),
+    ("payload_on", "pl_on"),
+    ("payload_press", "pl_prs"),
+    ("payload_unlock", "pl_unlk"),
+    ("platform", "p"),
+    ("state_class", "stat_cla"),
+    ("state_locked", "stat_locked"),
+    ("state_topic", "stat_t"),
+    ("state_unlocked", "stat_unlocked"),
+    ("supported_color_modes", "sup_clrm"),
+    ("topic", "t"),
+    ("unique_id", "uniq_id"),
+    ("unit_of_measurement", "unit_of_meas"),
+    ("value_template", "val_tpl"),
+];
+
+/// Keys of the device block and their abbreviated form.
+const DEVICE_ABBREVIATIONS: &[(&str, &str)] = &[
+    ("identifiers", "ids"),
+    ("manufacturer", "mf"),
+    ("model", "mdl"),
+    ("sw_version", "sw"),
+];
+
+/// Keys of the origin block and their abbreviated form.
+const ORIGIN_ABBREVIATIONS: &[(&str, &str)] = &[("sw_version", "sw"), ("support_url", "url")];
+
+/// Rewrites a discovery payload to use abbreviated keys and the `~` base topic.
+///
+/// Every topic starting with `base_topic` is shortened to `~`, which Home Assistant expands again on receive.
+pub fn abbreviate(payload: Value, base_topic: &str) -> Value {
+    match payload {
+        Value::Object(object) => {
+            let mut abbreviated = abbreviate_object(object, base_topic, ABBREVIATIONS);
+            abbreviated.insert(
+                BASE_TOPIC.to_string(),
+                Value::String(base_topic.to_string()),
+            );
+
+            Value::Object(abbreviated)
+        }
+        payload => payload,
+    }
+}
+
+fn abbreviate_object(
+    object: Map<String, Value>,
+    base_topic: &str,
+    abbreviations: &[(&str, &str)],
+) -> Map<String, Value> {
+    object
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(topic) if is_topic(&key) => {
+                    Value::String(shorten_topic(topic, base_topic))
+                }
+                Value::Object(device) if key == "device" => {
+                    Value::Object(abbreviate_object(device, base_topic, DEVICE_ABBREVIATIONS))
+                }
+                Value::Object(origin) if key == "origin" => {
+                    Value::Object(abbreviate_object(origin, base_topic, ORIGIN_ABBREVIATIONS))
+                }
+                Value::Object(object) => {
+                    Value::Object(abbreviate_object(object, base_topic, abbreviations))
+                }
+                Value::Array(values) => Value::Array(
+                    values
+                        .into_iter()
+                        .map(|value| match value {
+                            Value::Object(object) => {
+                                Value::Object(abbreviate_object(object, base_topic, abbreviations))
+                            }
+                            value => value,
+                        })
+                        .collect(),
+                ),
+                value => value,
+            };
+            (abbreviate_key(key, abbreviations), value)
+        })
+        .collect()
+}
+
+fn abbreviate_key(key: String, abbreviations: &[(&str, &str)]) -> String {
+    abbreviations
+        .iter()
+        .find(|(long, _)| *long == key)
+        .map(|(_, short)| short.to_string())
+        .unwrap_or(key)
+}
+
+fn is_topic(key: &str) -> bool {
+    key == "topic" || key.ends_with("_topic")
+}
+
+fn shorten_topic(topic: String, base_topic: &str) -> String {
+    match topic.strip_prefix(base_topic) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", BASE_TOPIC, rest),
+        _ => topic,
+    }
+}
@@ -0,0 +1,988 @@
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::config::{
+    Config, CustomCommandConfig, DiscoveryMode, HomeAssistantConfig, IdleBackend, IdleLevelConfig,
+    NightLightConfig, NotificationsConfig, SensorType,
+};
+use crate::core::mqtt::{MqttCommand, MqttMessage};
+use crate::core::state::{LockState, COLOR_TEMP_MODE};
+use crate::core::worker::Worker;
+use crate::modules::{
+    get_backlight_devices, to_mireds, BacklightDevice, ButtonClass, CustomCommandsModule,
+    NightLightModule, NotificationEvent, NotificationsModule, ScreenSaverInhibitModule,
+    SensorClass, SensorsModule, SessionLockModule, HASS_MAX_BRIGHTNESS, IDLE_COMPONENT,
+    LOCK_PAYLOAD, NIGHT_LIGHT_COMPONENT, SCREENSAVER_INHIBIT_COMPONENT, SESSION_LOCK_COMPONENT,
+    UNLOCK_PAYLOAD,
+};
+
+mod abbreviations;
+
+/// Time the broker gets to deliver retained discovery configs after subscribing
+const RETAINED_CONFIGS_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct HomeAssistantWorker {
+    mqtt_sender: UnboundedSender<MqttCommand>,
+    /// Only used to find the retained discovery configs of previous runs
+    mqtt_receiver: Option<broadcast::Receiver<MqttMessage>>,
+    entities: Vec<DiscoveryEntity>,
+}
+
+impl Worker for HomeAssistantWorker {
+    fn run(&mut self, config: &Config) -> BoxFuture<anyhow::Result<()>> {
+        let hass_config = config.hass.clone();
+        let topic = format!("desktop2mqtt/{}", hass_config.entity_id);
+        let device = Device::new(
+            format!("desktop2mqtt_{}", hass_config.entity_id),
+            hass_config.name.clone(),
+        );
+        let modules_config = config.modules.clone();
+        async move {
+            if let Some(idle) = modules_config.idle {
+                // The logind backend only reports changes, so the occupancy must not expire in between
+                let expire_after = match idle.backend {
+                    IdleBackend::Polling => Some((idle.poll_rate * 2).as_secs()),
+                    IdleBackend::Logind => None,
+                };
+                self.announce_occupancy(&hass_config, topic.clone(), device.clone(), expire_after)?;
+                self.announce_idle_sensors(
+                    &hass_config,
+                    topic.clone(),
+                    device.clone(),
+                    &idle.levels()?,
+                )?;
+            }
+            for backlight in get_backlight_devices(&modules_config.backlight) {
+                self.announce_backlight(&hass_config, topic.clone(), device.clone(), &backlight)?;
+            }
+            if let Some(night_light) = &modules_config.night_light {
+                self.announce_night_light(
+                    &hass_config,
+                    topic.clone(),
+                    device.clone(),
+                    night_light,
+                )?;
+            }
+            if modules_config.session_lock.is_some() {
+                self.announce_session_lock(&hass_config, topic.clone(), device.clone())?;
+            }
+            if modules_config.screensaver_inhibit.is_some() {
+                self.announce_screensaver_inhibit(&hass_config, topic.clone(), device.clone())?;
+            }
+            if let Some(notifications) = &modules_config.notifications {
+                self.announce_notifications(
+                    &hass_config,
+                    topic.clone(),
+                    device.clone(),
+                    notifications,
+                )?;
+            }
+            if modules_config.sensors.types.len() > 0 {
+                self.announce_sensors(
+                    &hass_config,
+                    topic.clone(),
+                    device.clone(),
+                    &modules_config.sensors.types,
+                )?;
+            }
+            if modules_config.custom_commands.len() > 0 {
+                self.announce_custom_commands(
+                    &hass_config,
+                    topic.clone(),
+                    device.clone(),
+                    &modules_config.custom_commands,
+                )?;
+            }
+            self.publish_discovery(&hass_config, device).await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+impl HomeAssistantWorker {
+    pub fn new(
+        mqtt_sender: UnboundedSender<MqttCommand>,
+        mqtt_receiver: broadcast::Receiver<MqttMessage>,
+    ) -> Self {
+        HomeAssistantWorker {
+            mqtt_sender,
+            mqtt_receiver: Some(mqtt_receiver),
+            entities: Vec::new(),
+        }
+    }
+
+    fn add_entity(&mut self, component: &'static str, object_id: String, message: ConfigMessage) {
+        self.entities.push(DiscoveryEntity {
+            component,
+            object_id,
+            message,
+        });
+    }
+
+    async fn publish_discovery(
+        &mut self,
+        config: &HomeAssistantConfig,
+        device: Device,
+    ) -> anyhow::Result<()> {
+        let entities = std::mem::take(&mut self.entities);
+        let device_topic = format!("homeassistant/device/{}/config", config.entity_id);
+        let retained = self
+            .retained_configs(&config.entity_id, &device_topic)
+            .await?;
+        match config.discovery_mode {
+            DiscoveryMode::Entity => {
+                // The device config of a previous run in device mode would duplicate every entity
+                if retained.contains(&device_topic) {
+                    self.mqtt_sender.send(MqttCommand::clear(device_topic))?;
+                }
+                for entity in entities {
+                    let config_topic = entity.config_topic(&config.entity_id);
+                    self.publish_config(
+                        config,
+                        config_topic,
+                        serde_json::to_value(&entity.message)?,
+                    )?;
+                }
+            }
+            DiscoveryMode::Device => {
+                // Home Assistant keeps the entities of migrated per entity discovery topics
+                // when they are flagged before the device config is published and removed afterwards.
+                let legacy_topics: Vec<_> = entities
+                    .iter()
+                    .map(|entity| entity.config_topic(&config.entity_id))
+                    .filter(|topic| retained.contains(topic))
+                    .collect();
+                for topic in &legacy_topics {
+                    self.mqtt_sender.send(MqttCommand::new_json(
+                        topic.clone(),
+                        &json!({ "migrate_discovery": true }),
+                    )?)?;
+                }
+                let components = entities
+                    .into_iter()
+                    .map(|mut entity| {
+                        entity.message.device = None;
+                        let component = DeviceComponent {
+                            platform: entity.component.to_string(),
+                            message: entity.message,
+                        };
+                        (entity.object_id, component)
+                    })
+                    .collect();
+                let msg = DeviceConfigMessage {
+                    device,
+                    origin: Origin::default(),
+                    components,
+                };
+                self.publish_config(config, device_topic, serde_json::to_value(&msg)?)?;
+                for topic in legacy_topics {
+                    self.mqtt_sender.send(MqttCommand::clear(topic))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collects the discovery topics of this device which currently hold a retained config.
+    async fn retained_configs(
+        &mut self,
+        entity_id: &str,
+        device_topic: &str,
+    ) -> anyhow::Result<HashSet<String>> {
+        let mut retained = HashSet::new();
+        let mut receiver = match self.mqtt_receiver.take() {
+            Some(receiver) => receiver,
+            None => return Ok(retained),
+        };
+        let entity_topics = format!("homeassistant/+/{}/+/config", entity_id);
+        self.mqtt_sender
+            .send(MqttCommand::subscribe(entity_topics))?;
+        self.mqtt_sender
+            .send(MqttCommand::subscribe(device_topic.to_string()))?;
+        let deadline = tokio::time::Instant::now() + RETAINED_CONFIGS_TIMEOUT;
+        loop {
+            let msg = match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Ok(msg)) => msg,
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) | Err(_) => break,
+            };
+            if !is_config_topic(&msg.topic, entity_id) {
+                continue;
+            }
+            // Cleared configs and pending migrations don't need to be migrated again
+            if msg.payload.is_empty() || msg.payload.contains("migrate_discovery") {
+                retained.remove(&msg.topic);
+            } else {
+                retained.insert(msg.topic);
+            }
+        }
+        log::debug!("Found retained discovery configs {:?}", retained);
+
+        Ok(retained)
+    }
+
+    fn publish_config(
+        &self,
+        config: &HomeAssistantConfig,
+        config_topic: String,
+        msg: serde_json::Value,
+    ) -> anyhow::Result<()> {
+        let msg = if config.abbreviated_discovery {
+            let base_topic = format!("desktop2mqtt/{}", config.entity_id);
+            abbreviations::abbreviate(msg, &base_topic)
+        } else {
+            msg
+        };
+        self.mqtt_sender
+            .send(MqttCommand::new_json(config_topic, &msg)?)?;
+
+        Ok(())
+    }
+
+    fn announce_backlight(
+        &mut self,
+        config: &HomeAssistantConfig,
+        topic: String,
+        device: Device,
+        backlight: &BacklightDevice,
+    ) -> anyhow::Result<()> {
+        let state_topic = backlight.state_topic(&config.entity_id);
+        let command_topic = backlight.command_topic(&config.entity_id);
+        let object_id = backlight.object_id();
+        let msg = ConfigMessage::light(
+            format!("{} {}", &config.name, &backlight.name),
+            format!("{}_{}_desktop2mqtt", config.entity_id, object_id),
+            device,
+            topic.clone(),
+            LightConfig {
+                state_topic,
+                command_topic: command_topic.clone(),
+                brightness: true,
+                brightness_scale: Some(HASS_MAX_BRIGHTNESS),
+                supported_color_modes: None,
+                max_mireds: None,
+                min_mireds: None,
+                schema: "json".to_string(),
+            },
+        )
+        .with_module_availability(&topic, &object_id);
+
+        self.mqtt_sender
+            .send(MqttCommand::subscribe(command_topic))?;
+        if let Some(legacy_topic) = backlight.legacy_command_topic(&config.entity_id) {
+            self.mqtt_sender
+                .send(MqttCommand::subscribe(legacy_topic))?;
+        }
+        self.add_entity("light", object_id, msg);
+
+        Ok(())
+    }
+
+    fn announce_night_light(
+        &mut self,
+        config: &HomeAssistantConfig,
+        topic: String,
+        device: Device,
+        night_light: &NightLightConfig,
+    ) -> anyhow::Result<()> {
+        let msg = ConfigMessage::light(
+            format!("{} Night Light", &config.name),
+            format!("{}_night_light_desktop2mqtt", config.entity_id),
+            device,
+            topic.clone(),
+            LightConfig {
+                state_topic: NightLightModule::state_topic(&config.entity_id),
+                command_topic: NightLightModule::command_topic(&config.entity_id),
+                brightness: false,
+                brightness_scale: None,
+                supported_color_modes: Some(vec![COLOR_TEMP_MODE.to_string()]),
+                max_mireds: Some(to_mireds(night_light.min_temperature)),
+                min_mireds: Some(to_mireds(night_light.max_temperature)),
+                schema: "json".to_string(),
+            },
+        )
+        .with_module_availability(&topic, NIGHT_LIGHT_COMPONENT);
+
+        self.add_entity("light", "night_light".to_string(), msg);
+
+        Ok(())
+    }
+
+    fn announce_session_lock(
+        &mut self,
+        config: &HomeAssistantConfig,
+        topic: String,
+        device: Device,
+    ) -> anyhow::Result<()> {
+        let msg = ConfigMessage::lock(
+            format!("{} Session", &config.name),
+            format!("{}_session_lock_desktop2mqtt", config.entity_id),
+            device,
+            topic.clone(),
+            LockConfig {
+                state_topic: topic.clone(),
+                command_topic: SessionLockModule::command_topic(&config.entity_id),
+                value_template: "{{ value_json.session_lock }}".to_string(),
+                payload_lock: LOCK_PAYLOAD.to_string(),
+                payload_unlock: UNLOCK_PAYLOAD.to_string(),
+                state_locked: LockState::Locked.to_string(),
+                state_unlocked: LockState::Unlocked.to_string(),
+            },
+        )
+        .with_module_availability(&topic, SESSION_LOCK_COMPONENT);
+
+        self.add_entity("lock", "session_lock".to_string(), msg);
+
+        Ok(())
+    }
+
+    fn announce_screensaver_inhibit(
+        &mut self,
+        config: &HomeAssistantConfig,
+        topic: String,
+        device: Device,
+    ) -> anyhow::Result<()> {
+        let msg = ConfigMessage::switch(
+            format!("{} Screen Saver Inhibit", &config.name),
+            format!("{}_screensaver_inhibit_desktop2mqtt", config.entity_id),
+            device,
+            topic.clone(),
+            SwitchConfig {
+                state_topic: topic.clone(),
+                command_topic: ScreenSaverInhibitModule::command_topic(&config.entity_id),
+                value_template: "{{ value_json.screensaver_inhibit }}".to_string(),
+                payload_on: "ON".to_string(),
+                payload_off: "OFF".to_string(),
+                icon: Some("mdi:sleep-off".to_string()),
+            },
+        )
+        .with_module_availability(&topic, SCREENSAVER_INHIBIT_COMPONENT);
+
+        self.add_entity("switch", "screensaver_inhibit".to_string(), msg);
+
+        Ok(())
+    }
+
+    fn announce_notifications(
+        &mut self,
+        config: &HomeAssistantConfig,
+        topic: String,
+        device: Device,
+        notifications: &NotificationsConfig,
+    ) -> anyhow::Result<()> {
+        let notify = ConfigMessage::notify(
+            config.name.clone(),
+            format!("{}_notify_desktop2mqtt", config.entity_id),
+            device.clone(),
+            topic.clone(),
+            NotifyConfig {
+                command_topic: NotificationsModule::topic(&config.entity_id, notifications),
+                command_template: "{\"message\": {{ value | tojson }}}".to_string(),
+            },
+        );
+        let event = ConfigMessage::event(
+            format!("{} Notification", &config.name),
+            format!("{}_notification_desktop2mqtt", config.entity_id),
+            device.clone(),
+            topic.clone(),
+            EventConfig {
+                state_topic: NotificationsModule::response_topic(&config.entity_id, notifications),
+                event_types: NotificationEvent::ALL
+                    .iter()
+                    .map(|event| event.as_str().to_string())
+                    .collect(),
+                icon: Some("mdi:message-reply-text".to_string()),
+            },
+        );
+        let do_not_disturb = ConfigMessage::switch(
+            format!("{} Do Not Disturb", &config.name),
+            format!("{}_do_not_disturb_desktop2mqtt", config.entity_id),
+            device.clone(),
+            topic.clone(),
+            SwitchConfig {
+                state_topic: topic.clone(),
+                command_topic: NotificationsModule::do_not_disturb_topic(&config.entity_id),
+                value_template: "{{ value_json.do_not_disturb }}".to_string(),
+                payload_on: "ON".to_string(),
+                payload_off: "OFF".to_string(),
+                icon: Some("mdi:bell-off".to_string()),
+            },
+        );
+        let queued = ConfigMessage::sensor(
+            format!("{} Queued Notifications", &config.name),
+            format!("{}_queued_notifications_desktop2mqtt", config.entity_id),
+            device,
+            topic.clone(),
+            SensorConfig {
+                state_topic: topic,
+                value_template: "{{ value_json.queued_notifications }}".to_string(),
+                icon: Some("mdi:bell-badge".to_string()),
+                ..Default::default()
+            },
+        );
+
+        self.add_entity("notify", "notify".to_string(), notify);
+        self.add_entity("event", "notification".to_string(), event);
+        self.add_entity("switch", "do_not_disturb".to_string(), do_not_disturb);
+        self.add_entity("sensor", "queued_notifications".to_string(), queued);
+
+        Ok(())
+    }
+
+    fn announce_occupancy(
+        &mut self,
+        config: &HomeAssistantConfig,
+        topic: String,
+        device: Device,
+        expire_after: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let msg = ConfigMessage::binary_sensor(
+            format!("{} Occupancy", &config.name),
+            format!("{}_occupancy_desktop2mqtt", config.entity_id),
+            device,
+            topic.clone(),
+            BinarySensorConfig {
+                state_topic: topic.clone(),
+                device_class: "occupancy".to_string().into(),
+                value_template: "{{ value_json.occupancy }}".to_string(),
+                expire_after,
+                ..Default::default()
+            },
+        )
+        .with_module_availability(&topic, IDLE_COMPONENT);
+
+        self.add_entity("binary_sensor", "occupancy".to_string(), msg);
+
+        Ok(())
+    }
+
+    fn announce_idle_sensors(
+        &mut self,
+        config: &HomeAssistantConfig,
+        topic: String,
+        device: Device,
+        levels: &[IdleLevelConfig],
+    ) -> anyhow::Result<()> {
+        let idle_level = ConfigMessage::sensor(
+            format!("{} Idle Level", &config.name),
+            format!("{}_idle_level_desktop2mqtt", config.entity_id),
+            device.clone(),
+            topic.clone(),
+            SensorConfig {
+                state_topic: topic.clone(),
+                device_class: Some("enum".to_string()),
+                value_template: "{{ value_json.idle_level }}".to_string(),
+                options: Some(levels.iter().map(|level| level.name.clone()).collect()),
+                ..Default::default()
+            },
+        )
+        .with_module_availability(&topic, IDLE_COMPONENT);
+        let idle_time = ConfigMessage::sensor(
+            format!("{} Idle Time", &config.name),
+            format!("{}_idle_time_desktop2mqtt", config.entity_id),
+            device.clone(),
+            topic.clone(),
+            SensorConfig {
+                state_topic: topic.clone(),
+                device_class: Some("duration".to_string()),
+                value_template: "{{ value_json.idle_time }}".to_string(),
+                unit_of_measurement: Some("s".to_string()),
+                ..Default::default()
+            },
+        )
+        .with_module_availability(&topic, IDLE_COMPONENT);
+        let last_activity = ConfigMessage::sensor(
+            format!("{} Last Activity", &config.name),
+            format!("{}_last_activity_desktop2mqtt", config.entity_id),
+            device,
+            topic.clone(),
+            SensorConfig {
+                state_topic: topic.clone(),
+                device_class: Some("timestamp".to_string()),
+                value_template: "{{ value_json.last_activity }}".to_string(),
+                ..Default::default()
+            },
+        )
+        .with_module_availability(&topic, IDLE_COMPONENT);
+
+        self.add_entity("sensor", "idle_level".to_string(), idle_level);
+        self.add_entity("sensor", "idle_time".to_string(), idle_time);
+        self.add_entity("sensor", "last_activity".to_string(), last_activity);
+
+        Ok(())
+    }
+
+    fn announce_sensors(
+        &mut self,
+        config: &HomeAssistantConfig,
+        topic: String,
+        device: Device,
+        enabled_sensors: &[SensorType],
+    ) -> anyhow::Result<()> {
+        for sensor in SensorsModule::get_sensors(enabled_sensors)? {
+            let msg = ConfigMessage::sensor(
+                format!("{} {}", &config.name, sensor.name),
+                format!("{}_{}_desktop2mqtt", config.entity_id, sensor.id),
+                device.clone(),
+                topic.clone(),
+                SensorConfig {
+                    state_topic: topic.clone(),
+                    device_class: sensor.class.to_hass_class(),
+                    value_template: format!("{{{{ value_json.sensors.{} }}}}", sensor.id),
+                    unit_of_measurement: sensor.class.to_unit(),
+                    icon: sensor.icon,
+                    ..Default::default()
+                },
+            )
+            .with_module_availability(&topic, &sensor.id);
+
+            self.add_entity("sensor", sensor.id, msg);
+        }
+        Ok(())
+    }
+
+    fn announce_custom_commands(
+        &mut self,
+        config: &HomeAssistantConfig,
+        topic: String,
+        device: Device,
+        custom_commands: &[CustomCommandConfig],
+    ) -> anyhow::Result<()> {
+        for command in CustomCommandsModule::get_commands(&config.entity_id, custom_commands) {
+            let msg = ConfigMessage::button(
+                format!("{} {}", &config.name, command.name),
+                format!("{}_{}_desktop2mqtt", config.entity_id, command.id),
+                device.clone(),
+                topic.clone(),
+                ButtonConfig {
+                    device_class: command.class.to_hass_class(),
+                    icon: command.icon,
+                    command_topic: command.topic,
+                    ..Default::default()
+                },
+            );
+
+            self.add_entity("button", command.id, msg);
+        }
+        Ok(())
+    }
+}
+
+trait ToHassClass {
+    fn to_hass_class(&self) -> Option<String>;
+
+    fn to_unit(&self) -> Option<String>;
+}
+
+impl ToHassClass for SensorClass {
+    fn to_hass_class(&self) -> Option<String> {
+        match self {
+            SensorClass::Generic => None,
+            SensorClass::Battery => Some("battery".to_string()),
+            SensorClass::Temperature => Some("temperature".to_string()),
+        }
+    }
+
+    fn to_unit(&self) -> Option<String> {
+        match self {
+            SensorClass::Generic => Some("%".into()),
+            SensorClass::Battery => Some("%".into()),
+            SensorClass::Temperature => Some("°C".into()),
+        }
+    }
+}
+
+impl ToHassClass for ButtonClass {
+    fn to_hass_class(&self) -> Option<String> {
+        match self {
+            Self::Generic => None,
+            Self::Restart => Some("restart".to_string()),
+            Self::Update => Some("update".to_string()),
+        }
+    }
+
+    fn to_unit(&self) -> Option<String> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigMessage {
+    pub availability: Vec<Availability>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub availability_mode: Option<String>,
+    pub name: String,
+    pub unique_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<Device>,
+    pub json_attributes_topic: String,
+    #[serde(flatten)]
+    pub binary_sensor: Option<BinarySensorConfig>,
+    #[serde(flatten)]
+    pub sensor: Option<SensorConfig>,
+    #[serde(flatten)]
+    pub light: Option<LightConfig>,
+    #[serde(flatten)]
+    pub button: Option<ButtonConfig>,
+    #[serde(flatten)]
+    pub lock: Option<LockConfig>,
+    #[serde(flatten)]
+    pub switch: Option<SwitchConfig>,
+    #[serde(flatten)]
+    pub event: Option<EventConfig>,
+    #[serde(flatten)]
+    pub notify: Option<NotifyConfig>,
+}
+
+impl ConfigMessage {
+    fn new(name: String, id: String, device: Device, topic: String) -> Self {
+        ConfigMessage {
+            availability: vec![Availability::new(format!("{}/availability", topic))],
+            availability_mode: None,
+            name,
+            unique_id: id,
+            device: Some(device),
+            json_attributes_topic: topic,
+            binary_sensor: None,
+            sensor: None,
+            light: None,
+            button: None,
+            lock: None,
+            switch: None,
+            event: None,
+            notify: None,
+        }
+    }
+
+    fn binary_sensor(
+        name: String,
+        id: String,
+        device: Device,
+        topic: String,
+        config: BinarySensorConfig,
+    ) -> Self {
+        ConfigMessage {
+            binary_sensor: Some(config),
+            ..Self::new(name, id, device, topic)
+        }
+    }
+
+    fn sensor(
+        name: String,
+        id: String,
+        device: Device,
+        topic: String,
+        config: SensorConfig,
+    ) -> Self {
+        ConfigMessage {
+            sensor: Some(config),
+            ..Self::new(name, id, device, topic)
+        }
+    }
+
+    fn light(name: String, id: String, device: Device, topic: String, config: LightConfig) -> Self {
+        ConfigMessage {
+            light: Some(config),
+            ..Self::new(name, id, device, topic)
+        }
+    }
+
+    fn button(
+        name: String,
+        id: String,
+        device: Device,
+        topic: String,
+        config: ButtonConfig,
+    ) -> Self {
+        ConfigMessage {
+            button: Some(config),
+            ..Self::new(name, id, device, topic)
+        }
+    }
+
+    fn switch(
+        name: String,
+        id: String,
+        device: Device,
+        topic: String,
+        config: SwitchConfig,
+    ) -> Self {
+        ConfigMessage {
+            switch: Some(config),
+            ..Self::new(name, id, device, topic)
+        }
+    }
+
+    fn event(name: String, id: String, device: Device, topic: String, config: EventConfig) -> Self {
+        ConfigMessage {
+            event: Some(config),
+            ..Self::new(name, id, device, topic)
+        }
+    }
+
+    fn notify(
+        name: String,
+        id: String,
+        device: Device,
+        topic: String,
+        config: NotifyConfig,
+    ) -> Self {
+        ConfigMessage {
+            notify: Some(config),
+            ..Self::new(name, id, device, topic)
+        }
+    }
+
+    fn lock(name: String, id: String, device: Device, topic: String, config: LockConfig) -> Self {
+        ConfigMessage {
+            lock: Some(config),
+            ..Self::new(name, id, device, topic)
+        }
+    }
+
+    /// Additionally ties the availability of this entity to the health of the given module component.
+    fn with_module_availability(mut self, topic: &str, component: &str) -> Self {
+        self.availability
+            .push(Availability::new(availability_topic(topic, component)));
+        self.availability_mode = Some("all".to_string());
+        self
+    }
+}
+
+/// Matches the per entity and device discovery topics of the given entity id.
+fn is_config_topic(topic: &str, entity_id: &str) -> bool {
+    let parts: Vec<_> = topic.split('/').collect();
+    match parts.as_slice() {
+        ["homeassistant", "device", id, "config"] => *id == entity_id,
+        ["homeassistant", _, id, _, "config"] => *id == entity_id,
+        _ => false,
+    }
+}
+
+struct DiscoveryEntity {
+    component: &'static str,
+    object_id: String,
+    message: ConfigMessage,
+}
+
+impl DiscoveryEntity {
+    fn config_topic(&self, entity_id: &str) -> String {
+        format!(
+            "homeassistant/{}/{}/{}/config",
+            self.component, entity_id, self.object_id
+        )
+    }
+}
+
+/// Single payload describing the device and all of its entities.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceConfigMessage {
+    pub device: Device,
+    pub origin: Origin,
+    pub components: BTreeMap<String, DeviceComponent>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceComponent {
+    pub platform: String,
+    #[serde(flatten)]
+    pub message: ConfigMessage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Origin {
+    pub name: String,
+    pub sw_version: String,
+    pub support_url: String,
+}
+
+impl Default for Origin {
+    fn default() -> Self {
+        Origin {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            sw_version: env!("CARGO_PKG_VERSION").to_string(),
+            support_url: env!("CARGO_PKG_REPOSITORY").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Availability {
+    pub topic: String,
+}
+
+impl Availability {
+    fn new(topic: String) -> Self {
+        Availability { topic }
+    }
+}
+
+/// Topic a module publishes the availability of one of its components to.
+pub fn availability_topic(topic: &str, component: &str) -> String {
+    format!("{}/availability/{}", topic, component)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BinarySensorConfig {
+    pub state_topic: String,
+    pub device_class: Option<String>,
+    pub value_template: String,
+    pub payload_off: bool,
+    pub payload_on: bool,
+    /// Defines the number of seconds after the sensor’s state expires, if it’s not updated.
+    pub expire_after: Option<u64>,
+}
+
+impl Default for BinarySensorConfig {
+    fn default() -> Self {
+        BinarySensorConfig {
+            state_topic: Default::default(),
+            device_class: None,
+            value_template: String::new(),
+            payload_on: true,
+            payload_off: false,
+            expire_after: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SensorConfig {
+    pub state_topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<String>,
+    pub value_template: String,
+    /// Defines the number of seconds after the sensor’s state expires, if it’s not updated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_of_measurement: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// Possible states of an `enum` sensor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<Vec<String>>,
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        SensorConfig {
+            state_topic: Default::default(),
+            device_class: None,
+            value_template: String::new(),
+            expire_after: None,
+            unit_of_measurement: None,
+            icon: None,
+            options: None,
+        }
+    }
+}
+
+/// Light using the json schema, which always supports `transition` in commands
+#[derive(Debug, Clone, Serialize)]
+pub struct LightConfig {
+    pub state_topic: String,
+    pub command_topic: String,
+    pub brightness: bool,
+    /// Brightness value reported for full brightness
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness_scale: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supported_color_modes: Option<Vec<String>>,
+    /// Warmest colour temperature in mireds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_mireds: Option<u32>,
+    /// Coldest colour temperature in mireds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_mireds: Option<u32>,
+    pub schema: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ButtonConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<String>,
+    /// Defines the number of seconds after the sensor’s state expires, if it’s not updated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    pub command_topic: String,
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        Self {
+            device_class: None,
+            icon: None,
+            command_topic: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockConfig {
+    pub state_topic: String,
+    pub command_topic: String,
+    pub value_template: String,
+    pub payload_lock: String,
+    pub payload_unlock: String,
+    pub state_locked: String,
+    pub state_unlocked: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SwitchConfig {
+    pub state_topic: String,
+    pub command_topic: String,
+    pub value_template: String,
+    pub payload_on: String,
+    pub payload_off: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
+
+/// Event entity reading `event_type` from the json payloads of its state topic
+#[derive(Debug, Clone, Serialize)]
+pub struct EventConfig {
+    pub state_topic: String,
+    pub event_types: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
+
+/// Notify entity sending its message as plain notification, advanced fields require the json topic
+#[derive(Debug, Clone, Serialize)]
+pub struct NotifyConfig {
+    pub command_topic: String,
+    pub command_template: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Device {
+    pub identifiers: String,
+    pub name: String,
+    pub manufacturer: String,
+    pub model: String,
+    pub sw_version: String,
+}
+
+impl Device {
+    pub fn new(id: String, name: String) -> Self {
+        Device {
+            identifiers: id,
+            name,
+            manufacturer: "Max Jöhnk".to_string(),
+            model: env!("CARGO_PKG_NAME").to_string(),
+            sw_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
@@ -0,0 +1,615 @@
+use std::collections::HashMap;
+
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use serde_json::json;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::config::Config;
+use crate::core::mqtt::{MqttCommand, MqttMessage};
+use crate::core::state::{DesktopState, LockState};
+use crate::core::worker::Worker;
+use crate::modules::{
+    get_backlight_devices, to_mireds, BacklightDevice, CustomCommandsModule, NightLightModule,
+    NotificationsModule, ScreenSaverInhibitModule, SensorClass, SensorsModule, SessionLockModule,
+    HASS_MAX_BRIGHTNESS, LOCK_PAYLOAD, UNLOCK_PAYLOAD,
+};
+
+const HOMIE_VERSION: &str = "4.0";
+const HOMIE_BASE_TOPIC: &str = "homie";
+
+const IDLE_NODE: &str = "idle";
+const SENSORS_NODE: &str = "sensors";
+const COMMANDS_NODE: &str = "commands";
+const SESSION_NODE: &str = "session";
+const SCREENSAVER_NODE: &str = "screensaver";
+const NIGHT_LIGHT_NODE: &str = "night-light";
+const NOTIFICATIONS_NODE: &str = "notifications";
+
+/// Describes the device following the [Homie 4 convention](https://homieiot.github.io/specification/spec-core-v4_0_0/)
+/// and mirrors the desktop state onto the property topics.
+pub struct HomieWorker {
+    mqtt_sender: UnboundedSender<MqttCommand>,
+    mqtt_events: broadcast::Sender<MqttMessage>,
+    mqtt_receiver: broadcast::Receiver<MqttMessage>,
+    state_receiver: broadcast::Receiver<DesktopState>,
+}
+
+impl HomieWorker {
+    pub fn new(
+        mqtt_sender: UnboundedSender<MqttCommand>,
+        mqtt_events: broadcast::Sender<MqttMessage>,
+        state_receiver: broadcast::Receiver<DesktopState>,
+    ) -> Self {
+        HomieWorker {
+            mqtt_sender,
+            mqtt_receiver: mqtt_events.subscribe(),
+            mqtt_events,
+            state_receiver,
+        }
+    }
+
+    fn publish(&self, topic: String, payload: impl Into<String>) -> anyhow::Result<()> {
+        self.mqtt_sender.send(MqttCommand::Emit(MqttMessage {
+            topic,
+            payload: payload.into(),
+        }))?;
+
+        Ok(())
+    }
+
+    fn announce(&self, device: &HomieDevice) -> anyhow::Result<()> {
+        self.publish(format!("{}/$state", device.topic), "init")?;
+        self.publish(format!("{}/$homie", device.topic), HOMIE_VERSION)?;
+        self.publish(format!("{}/$name", device.topic), device.name.clone())?;
+        self.publish(format!("{}/$extensions", device.topic), "")?;
+        self.publish(format!("{}/$nodes", device.topic), join_ids(&device.nodes))?;
+        for node in &device.nodes {
+            let node_topic = format!("{}/{}", device.topic, node.id);
+            self.publish(format!("{}/$name", node_topic), node.name.clone())?;
+            self.publish(format!("{}/$type", node_topic), node.node_type.clone())?;
+            self.publish(
+                format!("{}/$properties", node_topic),
+                join_ids(&node.properties),
+            )?;
+            for property in &node.properties {
+                let property_topic = format!("{}/{}", node_topic, property.id);
+                self.publish(format!("{}/$name", property_topic), property.name.clone())?;
+                self.publish(format!("{}/$datatype", property_topic), property.datatype)?;
+                self.publish(
+                    format!("{}/$settable", property_topic),
+                    property.set_target.is_some().to_string(),
+                )?;
+                self.publish(
+                    format!("{}/$retained", property_topic),
+                    property.retained.to_string(),
+                )?;
+                if let Some(format) = &property.format {
+                    self.publish(format!("{}/$format", property_topic), format.clone())?;
+                }
+                if let Some(unit) = &property.unit {
+                    self.publish(format!("{}/$unit", property_topic), unit.clone())?;
+                }
+                if property.set_target.is_some() {
+                    self.mqtt_sender
+                        .send(MqttCommand::subscribe(format!("{}/set", property_topic)))?;
+                }
+            }
+        }
+        self.publish(format!("{}/$state", device.topic), "ready")?;
+
+        Ok(())
+    }
+
+    fn publish_state(
+        &self,
+        device: &HomieDevice,
+        state: &DesktopState,
+        published: &mut HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        for (node, property, value) in property_values(state) {
+            let property = to_homie_id(&property);
+            if !device.has_property(&node, &property) {
+                continue;
+            }
+            let topic = format!("{}/{}/{}", device.topic, node, property);
+            if published.get(&topic) == Some(&value) {
+                continue;
+            }
+            published.insert(topic.clone(), value.clone());
+            self.publish(topic, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Maps a value set on a settable homie property back onto the topic of the module owning it.
+    fn forward_set(&self, device: &HomieDevice, msg: &MqttMessage) -> anyhow::Result<()> {
+        let property = device.nodes.iter().find_map(|node| {
+            node.properties.iter().find(|property| {
+                msg.topic == format!("{}/{}/{}/set", device.topic, node.id, property.id)
+            })
+        });
+        let target = match property.and_then(|property| property.set_target.as_ref()) {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let forwarded = match target {
+            SetTarget::BacklightPower(topic) => {
+                let power = match parse_boolean(&msg.payload) {
+                    Some(true) => "ON",
+                    Some(false) => "OFF",
+                    None => return Ok(()),
+                };
+                MqttMessage {
+                    topic: topic.clone(),
+                    payload: json!({ "state": power }).to_string(),
+                }
+            }
+            SetTarget::BacklightBrightness(topic) => {
+                let brightness: u32 = match msg.payload.trim().parse() {
+                    Ok(brightness) => brightness,
+                    Err(err) => {
+                        log::warn!("Invalid homie brightness {}: {:?}", msg.payload, err);
+                        return Ok(());
+                    }
+                };
+                MqttMessage {
+                    topic: topic.clone(),
+                    payload: json!({ "brightness": brightness }).to_string(),
+                }
+            }
+            SetTarget::NightLightPower(topic) => {
+                let power = match parse_boolean(&msg.payload) {
+                    Some(true) => "ON",
+                    Some(false) => "OFF",
+                    None => return Ok(()),
+                };
+                MqttMessage {
+                    topic: topic.clone(),
+                    payload: json!({ "state": power }).to_string(),
+                }
+            }
+            SetTarget::NightLightTemperature(topic) => {
+                let temperature: u32 = match msg.payload.trim().parse() {
+                    Ok(temperature) => temperature,
+                    Err(err) => {
+                        log::warn!(
+                            "Invalid homie colour temperature {}: {:?}",
+                            msg.payload,
+                            err
+                        );
+                        return Ok(());
+                    }
+                };
+                MqttMessage {
+                    topic: topic.clone(),
+                    payload: json!({ "color_temp": to_mireds(temperature) }).to_string(),
+                }
+            }
+            SetTarget::SessionLock(topic) => {
+                let payload = match parse_boolean(&msg.payload) {
+                    Some(true) => LOCK_PAYLOAD,
+                    Some(false) => UNLOCK_PAYLOAD,
+                    None => return Ok(()),
+                };
+                MqttMessage {
+                    topic: topic.clone(),
+                    payload: payload.to_string(),
+                }
+            }
+            SetTarget::Switch(topic) => {
+                let payload = match parse_boolean(&msg.payload) {
+                    Some(true) => "ON",
+                    Some(false) => "OFF",
+                    None => return Ok(()),
+                };
+                MqttMessage {
+                    topic: topic.clone(),
+                    payload: payload.to_string(),
+                }
+            }
+            SetTarget::Command(topic) => MqttMessage {
+                topic: topic.clone(),
+                payload: msg.payload.clone(),
+            },
+        };
+        log::debug!("Forwarding homie set {:?} to {:?}", msg, forwarded);
+        self.mqtt_events.send(forwarded)?;
+
+        Ok(())
+    }
+}
+
+/// Parses a homie boolean, which is either `true` or `false`.
+fn parse_boolean(payload: &str) -> Option<bool> {
+    match payload {
+        "true" => Some(true),
+        "false" => Some(false),
+        payload => {
+            log::warn!("Invalid homie boolean {}", payload);
+            None
+        }
+    }
+}
+
+impl Worker for HomieWorker {
+    fn run(&mut self, config: &Config) -> BoxFuture<anyhow::Result<()>> {
+        let device = HomieDevice::new(config);
+        async move {
+            self.announce(&device)?;
+            let mut published = HashMap::new();
+            loop {
+                tokio::select! {
+                    state = self.state_receiver.recv() => match state {
+                        Ok(state) => self.publish_state(&device, &state, &mut published)?,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                    msg = self.mqtt_receiver.recv() => match msg {
+                        Ok(msg) if msg.topic.starts_with(&device.topic) => {
+                            self.forward_set(&device, &msg)?
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                }
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Topic the `$state` attribute of the homie device is published to.
+pub fn homie_state_topic(config: &Config) -> String {
+    format!("{}/{}/$state", HOMIE_BASE_TOPIC, config.hass.entity_id)
+}
+
+struct HomieDevice {
+    topic: String,
+    name: String,
+    nodes: Vec<HomieNode>,
+}
+
+struct HomieNode {
+    id: String,
+    name: String,
+    node_type: String,
+    properties: Vec<HomieProperty>,
+}
+
+struct HomieProperty {
+    id: String,
+    name: String,
+    datatype: &'static str,
+    format: Option<String>,
+    unit: Option<String>,
+    retained: bool,
+    set_target: Option<SetTarget>,
+}
+
+enum SetTarget {
+    BacklightPower(String),
+    BacklightBrightness(String),
+    NightLightPower(String),
+    /// Colour temperature in Kelvin
+    NightLightTemperature(String),
+    SessionLock(String),
+    Switch(String),
+    Command(String),
+}
+
+trait HomieId {
+    fn id(&self) -> &str;
+}
+
+impl HomieId for HomieNode {
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl HomieId for HomieProperty {
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl HomieDevice {
+    fn new(config: &Config) -> Self {
+        let entity_id = &config.hass.entity_id;
+        let modules = &config.modules;
+        let mut nodes = Vec::new();
+        if let Some(idle) = &modules.idle {
+            let levels = idle.levels().unwrap_or_default();
+            let levels: Vec<_> = levels.into_iter().map(|level| level.name).collect();
+            nodes.push(HomieNode {
+                id: IDLE_NODE.to_string(),
+                name: "Idle".to_string(),
+                node_type: "occupancy".to_string(),
+                properties: vec![
+                    HomieProperty::new("occupancy", "Occupancy", "boolean"),
+                    HomieProperty {
+                        format: Some(levels.join(",")),
+                        ..HomieProperty::new("level", "Idle Level", "enum")
+                    },
+                    HomieProperty {
+                        unit: Some("s".to_string()),
+                        ..HomieProperty::new("idle-time", "Idle Time", "integer")
+                    },
+                    HomieProperty::new("last-activity", "Last Activity", "datetime"),
+                ],
+            });
+        }
+        for backlight in get_backlight_devices(&modules.backlight) {
+            let command_topic = backlight.command_topic(entity_id);
+            nodes.push(HomieNode {
+                id: to_homie_id(&backlight.object_id()),
+                name: backlight.name.clone(),
+                node_type: "light".to_string(),
+                properties: vec![
+                    HomieProperty {
+                        set_target: Some(SetTarget::BacklightPower(command_topic.clone())),
+                        ..HomieProperty::new("power", "Power", "boolean")
+                    },
+                    HomieProperty {
+                        format: Some(format!("0:{}", HASS_MAX_BRIGHTNESS)),
+                        set_target: Some(SetTarget::BacklightBrightness(command_topic)),
+                        ..HomieProperty::new("brightness", "Brightness", "integer")
+                    },
+                ],
+            });
+        }
+        if !modules.sensors.types.is_empty() {
+            let sensors = SensorsModule::get_sensors(&modules.sensors.types).unwrap_or_default();
+            nodes.push(HomieNode {
+                id: SENSORS_NODE.to_string(),
+                name: "Sensors".to_string(),
+                node_type: "sensors".to_string(),
+                properties: sensors
+                    .into_iter()
+                    .map(|sensor| HomieProperty {
+                        unit: Some(sensor.class.to_homie_unit().to_string()),
+                        ..HomieProperty::new(&to_homie_id(&sensor.id), &sensor.name, "float")
+                    })
+                    .collect(),
+            });
+        }
+        if let Some(night_light) = &modules.night_light {
+            let command_topic = NightLightModule::command_topic(entity_id);
+            nodes.push(HomieNode {
+                id: NIGHT_LIGHT_NODE.to_string(),
+                name: "Night Light".to_string(),
+                node_type: "light".to_string(),
+                properties: vec![
+                    HomieProperty {
+                        set_target: Some(SetTarget::NightLightPower(command_topic.clone())),
+                        ..HomieProperty::new("power", "Power", "boolean")
+                    },
+                    HomieProperty {
+                        format: Some(format!(
+                            "{}:{}",
+                            night_light.min_temperature, night_light.max_temperature
+                        )),
+                        unit: Some("K".to_string()),
+                        set_target: Some(SetTarget::NightLightTemperature(command_topic)),
+                        ..HomieProperty::new("temperature", "Colour Temperature", "integer")
+                    },
+                ],
+            });
+        }
+        if modules.session_lock.is_some() {
+            let command_topic = SessionLockModule::command_topic(entity_id);
+            nodes.push(HomieNode {
+                id: SESSION_NODE.to_string(),
+                name: "Session".to_string(),
+                node_type: "lock".to_string(),
+                properties: vec![HomieProperty {
+                    set_target: Some(SetTarget::SessionLock(command_topic)),
+                    ..HomieProperty::new("locked", "Locked", "boolean")
+                }],
+            });
+        }
+        if modules.screensaver_inhibit.is_some() {
+            let command_topic = ScreenSaverInhibitModule::command_topic(entity_id);
+            nodes.push(HomieNode {
+                id: SCREENSAVER_NODE.to_string(),
+                name: "Screen Saver".to_string(),
+                node_type: "switch".to_string(),
+                properties: vec![HomieProperty {
+                    set_target: Some(SetTarget::Switch(command_topic)),
+                    ..HomieProperty::new("inhibit", "Inhibit", "boolean")
+                }],
+            });
+        }
+        if modules.notifications.is_some() {
+            let command_topic = NotificationsModule::do_not_disturb_topic(entity_id);
+            nodes.push(HomieNode {
+                id: NOTIFICATIONS_NODE.to_string(),
+                name: "Notifications".to_string(),
+                node_type: "notifications".to_string(),
+                properties: vec![
+                    HomieProperty {
+                        set_target: Some(SetTarget::Switch(command_topic)),
+                        ..HomieProperty::new("do-not-disturb", "Do Not Disturb", "boolean")
+                    },
+                    HomieProperty::new("queued", "Queued", "integer"),
+                ],
+            });
+        }
+        if !modules.custom_commands.is_empty() {
+            let commands = CustomCommandsModule::get_commands(entity_id, &modules.custom_commands);
+            nodes.push(HomieNode {
+                id: COMMANDS_NODE.to_string(),
+                name: "Commands".to_string(),
+                node_type: "commands".to_string(),
+                properties: commands
+                    .into_iter()
+                    .map(|command| HomieProperty {
+                        format: Some("PRESS".to_string()),
+                        retained: false,
+                        set_target: Some(SetTarget::Command(command.topic)),
+                        ..HomieProperty::new(&to_homie_id(&command.id), &command.name, "enum")
+                    })
+                    .collect(),
+            });
+        }
+
+        HomieDevice {
+            topic: format!("{}/{}", HOMIE_BASE_TOPIC, entity_id),
+            name: config.hass.name.clone(),
+            nodes,
+        }
+    }
+}
+
+impl HomieDevice {
+    fn has_property(&self, node: &str, property: &str) -> bool {
+        self.nodes
+            .iter()
+            .filter(|candidate| candidate.id == node)
+            .flat_map(|node| node.properties.iter())
+            .any(|candidate| candidate.id == property)
+    }
+}
+
+impl HomieProperty {
+    fn new(id: &str, name: &str, datatype: &'static str) -> Self {
+        HomieProperty {
+            id: id.to_string(),
+            name: name.to_string(),
+            datatype,
+            format: None,
+            unit: None,
+            retained: true,
+            set_target: None,
+        }
+    }
+}
+
+trait ToHomieUnit {
+    fn to_homie_unit(&self) -> &'static str;
+}
+
+impl ToHomieUnit for SensorClass {
+    fn to_homie_unit(&self) -> &'static str {
+        match self {
+            SensorClass::Generic => "%",
+            SensorClass::Battery => "%",
+            SensorClass::Temperature => "°C",
+        }
+    }
+}
+
+fn property_values(state: &DesktopState) -> Vec<(String, String, String)> {
+    let mut values = Vec::new();
+    if let Some(occupancy) = state.occupancy {
+        values.push((
+            IDLE_NODE.to_string(),
+            "occupancy".to_string(),
+            occupancy.to_string(),
+        ));
+    }
+    if let Some(level) = &state.idle_level {
+        values.push((IDLE_NODE.to_string(), "level".to_string(), level.clone()));
+    }
+    if let Some(idle_time) = state.idle_time {
+        values.push((
+            IDLE_NODE.to_string(),
+            "idle-time".to_string(),
+            idle_time.to_string(),
+        ));
+    }
+    if let Some(last_activity) = &state.last_activity {
+        values.push((
+            IDLE_NODE.to_string(),
+            "last-activity".to_string(),
+            last_activity.clone(),
+        ));
+    }
+    for (id, backlight) in &state.backlights {
+        let node = to_homie_id(&BacklightDevice::object_id_of(id));
+        let power: bool = backlight.power.into();
+        values.push((node.clone(), "power".to_string(), power.to_string()));
+        values.push((
+            node,
+            "brightness".to_string(),
+            backlight.brightness.to_string(),
+        ));
+    }
+    if let Some(night_light) = &state.night_light {
+        let power: bool = night_light.power.into();
+        let temperature = to_mireds(night_light.color_temp);
+        values.push((
+            NIGHT_LIGHT_NODE.to_string(),
+            "power".to_string(),
+            power.to_string(),
+        ));
+        values.push((
+            NIGHT_LIGHT_NODE.to_string(),
+            "temperature".to_string(),
+            temperature.to_string(),
+        ));
+    }
+    if let Some(lock) = state.session_lock {
+        let locked = lock == LockState::Locked;
+        values.push((
+            SESSION_NODE.to_string(),
+            "locked".to_string(),
+            locked.to_string(),
+        ));
+    }
+    if let Some(inhibit) = state.screensaver_inhibit {
+        let inhibit: bool = inhibit.into();
+        values.push((
+            SCREENSAVER_NODE.to_string(),
+            "inhibit".to_string(),
+            inhibit.to_string(),
+        ));
+    }
+    if let Some(do_not_disturb) = state.do_not_disturb {
+        let do_not_disturb: bool = do_not_disturb.into();
+        values.push((
+            NOTIFICATIONS_NODE.to_string(),
+            "do-not-disturb".to_string(),
+            do_not_disturb.to_string(),
+        ));
+    }
+    if let Some(queued) = state.queued_notifications {
+        values.push((
+            NOTIFICATIONS_NODE.to_string(),
+            "queued".to_string(),
+            queued.to_string(),
+        ));
+    }
+    for (name, value) in &state.sensors {
+        if let Some(value) = value {
+            values.push((SENSORS_NODE.to_string(), name.clone(), value.to_string()));
+        }
+    }
+
+    values
+}
+
+fn join_ids<T: HomieId>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.id())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Homie ids may only consist of lowercase letters, digits and hyphens.
+fn to_homie_id(id: &str) -> String {
+    let id: String = id
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+
+    id.trim_matches('-').to_string()
+}
@@ -1,9 +1,11 @@
 pub mod home_assistant;
+pub mod homie;
 pub mod mqtt;
 pub mod state;
 pub mod worker;
 
 pub use self::mqtt::*;
 pub use self::home_assistant::*;
+pub use self::homie::*;
 pub use self::state::*;
 pub use self::worker::*;
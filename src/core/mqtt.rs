@@ -37,14 +37,15 @@ impl<'a> Worker for MqttWorker<'a> {
             self.publish(MqttMessage {
                 topic: format!("desktop2mqtt/{}/availability", entity_id),
                 payload: "online".to_string(),
-            })
+            }, true)
             .await?;
             loop {
                 tokio::select! {
                     Some(msg) = self.receiver.recv() => {
                         match msg {
                             MqttCommand::Subscribe(topic) => self.subscribe(topic).await?,
-                            MqttCommand::Emit(msg) => self.publish(msg).await?,
+                            MqttCommand::Emit(msg) => self.publish(msg, true).await?,
+                            MqttCommand::Event(msg) => self.publish(msg, false).await?,
                         }
                     }
                     msg = self.client.read_subscriptions() => {
@@ -61,10 +62,10 @@ impl<'a> Worker for MqttWorker<'a> {
 }
 
 impl<'a> MqttWorker<'a> {
-    async fn publish(&self, msg: MqttMessage) -> anyhow::Result<()> {
+    async fn publish(&self, msg: MqttMessage, retain: bool) -> anyhow::Result<()> {
         log::debug!("Publishing mqtt message {:?}...", &msg);
         let mut publish = Publish::from(msg);
-        publish.set_retain(true);
+        publish.set_retain(retain);
 
         self.client.publish(&publish).await?;
 
@@ -101,6 +102,8 @@ impl<'a> MqttWorker<'a> {
 #[derive(Debug, Clone)]
 pub enum MqttCommand {
     Emit(MqttMessage),
+    /// Publishes the message without retaining it, late subscribers should not receive past events
+    Event(MqttMessage),
     Subscribe(String),
 }
 
@@ -120,6 +123,23 @@ impl MqttCommand {
         Ok(MqttCommand::Emit(MqttMessage { topic, payload }))
     }
 
+    pub fn event_json<TPayload: Serialize>(
+        topic: String,
+        payload: &TPayload,
+    ) -> anyhow::Result<Self> {
+        let payload = serde_json::to_string(&payload)?;
+
+        Ok(MqttCommand::Event(MqttMessage { topic, payload }))
+    }
+
+    /// Removes the retained message of the given topic.
+    pub fn clear(topic: String) -> Self {
+        MqttCommand::Emit(MqttMessage {
+            topic,
+            payload: String::new(),
+        })
+    }
+
     pub fn subscribe(topic: String) -> Self {
         MqttCommand::Subscribe(topic)
     }
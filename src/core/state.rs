@@ -1,49 +1,184 @@
 use futures_util::future::BoxFuture;
 use futures_util::FutureExt;
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use crate::core::mqtt::MqttCommand;
-use crate::config::Config;
-use crate::core::Worker;
+use crate::core::mqtt::{MqttCommand, MqttMessage};
+use crate::config::{get_state_file_path, Config};
+use crate::core::{availability_topic, Worker};
+use crate::modules::{get_backlight_devices, NightLightModule, SensorsModule};
 use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Interval in which changes to the state are written to disk
+const PERSIST_INTERVAL: Duration = Duration::from_secs(30);
+/// Color mode of lights controlling the colour temperature
+pub const COLOR_TEMP_MODE: &str = "color_temp";
 
 pub struct State {
     sender: UnboundedSender<MqttCommand>,
     receiver: UnboundedReceiver<StateChange>,
+    updates: broadcast::Sender<DesktopState>,
 }
 
 impl State {
     pub fn new(
         sender: UnboundedSender<MqttCommand>,
         receiver: UnboundedReceiver<StateChange>,
+        updates: broadcast::Sender<DesktopState>,
     ) -> Self {
-        State { sender, receiver }
+        State {
+            sender,
+            receiver,
+            updates,
+        }
+    }
+
+    fn publish(&self, topic: &str, state: &DesktopState) -> anyhow::Result<()> {
+        self.sender
+            .send(MqttCommand::new_json(topic.to_string(), state)?)?;
+        // Nobody listening for state updates is not an error
+        let _ = self.updates.send(state.clone());
+
+        Ok(())
+    }
+
+    /// Publishes the state of a light entity which reads its own state topic
+    fn publish_light(&self, topic: &str, state: &impl Serialize) -> anyhow::Result<()> {
+        self.sender
+            .send(MqttCommand::new_json(topic.to_string(), state)?)?;
+
+        Ok(())
     }
 }
 
 impl Worker for State {
     fn run(&mut self, config: &Config) -> BoxFuture<anyhow::Result<()>> {
-        let mut state = DesktopState::default();
+        let state_file = get_state_file_path();
+        let sensors = SensorsModule::get_sensors(&config.modules.sensors.types);
+        let backlights = get_backlight_devices(&config.modules.backlight);
+        let entity_id = config.hass.entity_id.clone();
+        let night_light_topic = NightLightModule::state_topic(&entity_id);
+        let mut availability = HashMap::new();
         let topic = format!("desktop2mqtt/{}", config.hass.entity_id);
         async move {
-            self.sender
-                .send(MqttCommand::new_json(topic.clone(), &state)?)?;
-            while let Some(value) = self.receiver.recv().await {
-                log::debug!("Received state change {:?}", &value);
-                match value {
-                    StateChange::Idle(idle) => {
-                        state.occupancy = Some(!idle);
-                    }
-                    StateChange::Backlight { brightness, power } => {
-                        state.backlight_brightness = brightness;
-                        state.backlight_power = power.into();
+            let restored = match &state_file {
+                Some(path) => DesktopState::restore(path).await,
+                None => DesktopState::default(),
+            };
+            let mut state = PersistOnDrop {
+                path: state_file.clone(),
+                state: restored,
+            };
+            let sensor_ids: Vec<String> = sensors?.into_iter().map(|sensor| sensor.id).collect();
+            state.sensors.retain(|name, _| sensor_ids.contains(name));
+            for id in sensor_ids {
+                state.sensors.entry(id).or_insert(None);
+            }
+            state
+                .backlights
+                .retain(|id, _| backlights.iter().any(|device| &device.id == id));
+            self.publish(&topic, &state)?;
+            for device in &backlights {
+                if let Some(backlight) = state.backlights.get(&device.id) {
+                    self.publish_light(&device.state_topic(&entity_id), backlight)?;
+                }
+            }
+            if let Some(night_light) = &state.night_light {
+                self.publish_light(&night_light_topic, night_light)?;
+            }
+            let mut persist_interval = tokio::time::interval(PERSIST_INTERVAL);
+            // Only changes to values worth restoring are written before shutdown, polled values
+            // like the sensors would otherwise rewrite the state file every interval
+            let mut dirty = false;
+            loop {
+                tokio::select! {
+                    value = self.receiver.recv() => {
+                        let value = match value {
+                            Some(value) => value,
+                            None => break,
+                        };
+                        log::debug!("Received state change {:?}", &value);
+                        match value {
+                            StateChange::Idle(idle) => {
+                                state.occupancy = Some(!idle);
+                            }
+                            StateChange::IdleLevel(level) => {
+                                state.idle_level = Some(level);
+                            }
+                            StateChange::IdleTime(idle_time) => {
+                                state.idle_time = Some(idle_time.as_secs());
+                            }
+                            StateChange::LastActivity(timestamp) => {
+                                state.last_activity =
+                                    Some(humantime::format_rfc3339_seconds(timestamp).to_string());
+                            }
+                            StateChange::Backlight { device, power, brightness } => {
+                                let backlight = BacklightState {
+                                    power: power.into(),
+                                    brightness,
+                                };
+                                // The light entity of every device reads its own state topic
+                                if let Some(device) =
+                                    backlights.iter().find(|candidate| candidate.id == device)
+                                {
+                                    self.publish_light(&device.state_topic(&entity_id), &backlight)?;
+                                }
+                                dirty |= state.backlights.get(&device) != Some(&backlight);
+                                state.backlights.insert(device, backlight);
+                            }
+                            StateChange::NightLight { power, color_temp } => {
+                                let night_light = NightLightState {
+                                    power: power.into(),
+                                    color_mode: COLOR_TEMP_MODE.to_string(),
+                                    color_temp,
+                                };
+                                self.publish_light(&night_light_topic, &night_light)?;
+                                dirty |= state.night_light.as_ref() != Some(&night_light);
+                                state.night_light = Some(night_light);
+                            }
+                            StateChange::Sensor { name, value } => {
+                                state.sensors.insert(name, Some(value));
+                            }
+                            StateChange::SessionLock(locked) => {
+                                let locked = Some(locked.into());
+                                dirty |= state.session_lock != locked;
+                                state.session_lock = locked;
+                            }
+                            StateChange::ScreenSaverInhibit(inhibit) => {
+                                state.screensaver_inhibit = Some(inhibit.into());
+                            }
+                            StateChange::DoNotDisturb { enabled, queued } => {
+                                let enabled = Some(enabled.into());
+                                dirty |= state.do_not_disturb != enabled;
+                                state.do_not_disturb = enabled;
+                                state.queued_notifications = Some(queued);
+                            }
+                            StateChange::Availability { component, available } => {
+                                if availability.insert(component.clone(), available) != Some(available) {
+                                    let payload = if available { "online" } else { "offline" };
+                                    self.sender.send(MqttCommand::Emit(MqttMessage {
+                                        topic: availability_topic(&topic, &component),
+                                        payload: payload.to_string(),
+                                    }))?;
+                                }
+                                continue;
+                            }
+                        }
+                        self.publish(&topic, &state)?;
                     }
-                    StateChange::Sensor { name, value } => {
-                        state.sensors.insert(name, value);
+                    _ = persist_interval.tick(), if dirty => {
+                        if let Some(path) = &state_file {
+                            if let Err(err) = state.persist(path).await {
+                                log::warn!("Unable to persist state to {:?}: {:?}", path, err);
+                            }
+                        }
+                        dirty = false;
                     }
                 }
-                self.sender
-                    .send(MqttCommand::new_json(topic.clone(), &state)?)?;
             }
 
             Ok(())
@@ -55,28 +190,157 @@ impl Worker for State {
 #[derive(Debug, Clone, PartialEq)]
 pub enum StateChange {
     Idle(bool),
-    Backlight { power: bool, brightness: u32 },
+    /// Name of the currently reached idle level
+    IdleLevel(String),
+    /// Duration since the last user input
+    IdleTime(Duration),
+    /// Point in time of the last user input
+    LastActivity(SystemTime),
+    /// Power and brightness (0-255) of the backlight device with the given id
+    Backlight {
+        device: String,
+        power: bool,
+        brightness: u32,
+    },
+    /// Power and colour temperature in mireds of the night light
+    NightLight { power: bool, color_temp: u32 },
     Sensor { name: String, value: f32 },
+    SessionLock(bool),
+    ScreenSaverInhibit(bool),
+    /// Do not disturb mode of the notifications and the number of notifications it holds back
+    DoNotDisturb { enabled: bool, queued: usize },
+    /// Health of a single module component, published to its own availability topic
+    Availability { component: String, available: bool },
 }
 
-#[derive(Debug, Clone, Serialize, Default)]
+/// Last known state of the desktop, values which are not known yet are reported as `null`
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct DesktopState {
     pub occupancy: Option<bool>,
-    #[serde(rename = "state")]
-    pub backlight_power: PowerState,
-    #[serde(rename = "brightness")]
-    pub backlight_brightness: u32,
-    pub sensors: HashMap<String, f32>,
+    /// Name of the currently reached idle level
+    pub idle_level: Option<String>,
+    /// Seconds since the last user input
+    pub idle_time: Option<u64>,
+    /// RFC 3339 timestamp of the last user input
+    pub last_activity: Option<String>,
+    /// State of every backlight device by its id
+    pub backlights: HashMap<String, BacklightState>,
+    pub night_light: Option<NightLightState>,
+    pub sensors: HashMap<String, Option<f32>>,
+    pub session_lock: Option<LockState>,
+    pub screensaver_inhibit: Option<PowerState>,
+    pub do_not_disturb: Option<PowerState>,
+    /// Notifications held back while do not disturb is on
+    pub queued_notifications: Option<usize>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl DesktopState {
+    /// Loads the state persisted by a previous run, falling back to an unknown state.
+    async fn restore(path: &Path) -> Self {
+        let content = match tokio::fs::read(path).await {
+            Ok(content) => content,
+            Err(err) => {
+                log::debug!("No persisted state found at {:?}: {}", path, err);
+                return DesktopState::default();
+            }
+        };
+        match serde_json::from_slice(&content) {
+            Ok(state) => {
+                let mut state: DesktopState = state;
+                state.forget_volatile();
+                log::debug!("Restored state {:?}", &state);
+                state
+            }
+            Err(err) => {
+                log::warn!("Unable to restore persisted state from {:?}: {}", path, err);
+                DesktopState::default()
+            }
+        }
+    }
+
+    /// Marks values which only the running modules know as unknown until they are reported again.
+    fn forget_volatile(&mut self) {
+        self.occupancy = None;
+        self.idle_level = None;
+        self.idle_time = None;
+        self.screensaver_inhibit = None;
+        self.queued_notifications = None;
+    }
+
+    fn persist_blocking(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let content = serde_json::to_vec(self)?;
+        std::fs::write(path, content)?;
+
+        Ok(())
+    }
+
+    async fn persist(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let content = serde_json::to_vec(self)?;
+        tokio::fs::write(path, content).await?;
+        log::trace!("Persisted state to {:?}", path);
+
+        Ok(())
+    }
+}
+
+/// Persists the state once more when the state worker is dropped on shutdown.
+struct PersistOnDrop {
+    path: Option<PathBuf>,
+    state: DesktopState,
+}
+
+impl Deref for PersistOnDrop {
+    type Target = DesktopState;
+
+    fn deref(&self) -> &DesktopState {
+        &self.state
+    }
+}
+
+impl DerefMut for PersistOnDrop {
+    fn deref_mut(&mut self) -> &mut DesktopState {
+        &mut self.state
+    }
+}
+
+impl Drop for PersistOnDrop {
+    fn drop(&mut self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        // The runtime is shutting down, so the state is written synchronously
+        match self.state.persist_blocking(path) {
+            Ok(()) => log::trace!("Persisted state to {:?} on shutdown", path),
+            Err(err) => log::warn!("Unable to persist state to {:?}: {:?}", path, err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BacklightState {
     #[serde(rename = "state")]
     pub power: PowerState,
     pub brightness: u32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NightLightState {
+    #[serde(rename = "state")]
+    pub power: PowerState,
+    pub color_mode: String,
+    /// Colour temperature in mireds
+    pub color_temp: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PowerState {
     #[serde(rename = "ON")]
     On,
@@ -90,6 +354,33 @@ impl Default for PowerState {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockState {
+    #[serde(rename = "LOCKED")]
+    Locked,
+    #[serde(rename = "UNLOCKED")]
+    Unlocked,
+}
+
+impl fmt::Display for LockState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockState::Locked => write!(f, "LOCKED"),
+            LockState::Unlocked => write!(f, "UNLOCKED"),
+        }
+    }
+}
+
+impl From<bool> for LockState {
+    fn from(locked: bool) -> Self {
+        if locked {
+            LockState::Locked
+        } else {
+            LockState::Unlocked
+        }
+    }
+}
+
 impl From<bool> for PowerState {
     fn from(power: bool) -> Self {
         if power {
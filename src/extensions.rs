@@ -3,7 +3,17 @@ pub trait StringExt {
 }
 
 impl StringExt for String {
+    /// Lowercase id usable as topic level and object id, other characters are replaced with `-`
     fn to_slug(&self) -> String {
-        self.to_lowercase().replace(" ", "-")
+        self.to_lowercase()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '_' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect()
     }
 }
@@ -4,7 +4,7 @@ mod extensions;
 mod modules;
 mod options;
 
-use crate::config::{get_config, Config};
+use crate::config::{get_config, Config, DiscoveryProtocol};
 use crate::core::*;
 use crate::modules::*;
 use crate::options::CliOptions;
@@ -44,6 +44,18 @@ async fn go_offline(client: &Client, config: &Config) -> anyhow::Result<()> {
         topic: format!("desktop2mqtt/{}/availability", config.hass.entity_id),
         payload: "offline".to_string(),
     };
+    publish_retained(client, msg).await?;
+    if config.discovery == DiscoveryProtocol::Homie {
+        let msg = MqttMessage {
+            topic: homie_state_topic(config),
+            payload: "disconnected".to_string(),
+        };
+        publish_retained(client, msg).await?;
+    }
+    Ok(())
+}
+
+async fn publish_retained(client: &Client, msg: MqttMessage) -> anyhow::Result<()> {
     let mut publish = Publish::from(msg);
     publish.set_retain(true);
     client.publish(&publish).await?;
@@ -66,34 +78,74 @@ async fn run_loop(client: &mut Client, config: Config) -> anyhow::Result<()> {
     let (mqtt_sender, mqtt_receiver) = mpsc::unbounded_channel();
     let (mqtt_event_sender, _) = broadcast::channel(10);
     let (state_sender, state_receiver) = mpsc::unbounded_channel();
+    let (state_update_sender, _) = broadcast::channel(10);
 
     let mut mqtt_worker = MqttWorker::new(client, mqtt_receiver, mqtt_event_sender.clone());
-    let mut hass_discovery_worker = HomeAssistantWorker::new(mqtt_sender.clone());
-    let mut state = State::new(mqtt_sender.clone(), state_receiver);
+    let mut discovery_worker = match config.discovery {
+        DiscoveryProtocol::HomeAssistant => {
+            Box::new(HomeAssistantWorker::new(
+                mqtt_sender.clone(),
+                mqtt_event_sender.subscribe(),
+            )) as Box<dyn Worker>
+        }
+        DiscoveryProtocol::Homie => Box::new(HomieWorker::new(
+            mqtt_sender.clone(),
+            mqtt_event_sender.clone(),
+            state_update_sender.subscribe(),
+        )),
+    };
+    let mut state = State::new(
+        mqtt_sender.clone(),
+        state_receiver,
+        state_update_sender.clone(),
+    );
     let mut idle_module = IdleModule::new(state_sender.clone());
-    let mut backlight_module = if let Some(backlight) = config.modules.backlight {
+    let mut backlight_module = if config.modules.backlight.is_empty() {
+        Box::new(EmptyWorker) as Box<dyn LocalWorker>
+    } else {
         get_backlight_module(
             state_sender.clone(),
-            mqtt_event_sender.subscribe(),
-            backlight,
+            &mqtt_event_sender,
+            &state_update_sender,
+            &config.modules.backlight,
         )
-    } else {
-        Box::new(EmptyWorker) as Box<dyn LocalWorker>
     };
-    let mut notifications_module =
-        NotificationsModule::new(mqtt_event_sender.subscribe(), mqtt_sender.clone());
+    let mut notifications_module = NotificationsModule::new(
+        state_sender.clone(),
+        mqtt_event_sender.subscribe(),
+        mqtt_sender.clone(),
+        state_update_sender.subscribe(),
+    );
     let mut sensors_module = SensorsModule::new(state_sender.clone());
+    let mut session_lock_module = SessionLockModule::new(
+        state_sender.clone(),
+        mqtt_event_sender.subscribe(),
+        mqtt_sender.clone(),
+    );
+    let mut screensaver_inhibit_module = ScreenSaverInhibitModule::new(
+        state_sender.clone(),
+        mqtt_event_sender.subscribe(),
+        mqtt_sender.clone(),
+    );
+    let mut night_light_module = NightLightModule::new(
+        state_sender.clone(),
+        mqtt_event_sender.subscribe(),
+        mqtt_sender.clone(),
+    );
     let mut custom_commands_module =
         CustomCommandsModule::new(mqtt_event_sender.subscribe(), mqtt_sender);
 
     tokio::try_join!(
         mqtt_worker.run(&config),
-        hass_discovery_worker.run(&config),
+        discovery_worker.run(&config),
         state.run(&config),
         idle_module.run(&config),
         backlight_module.run(&config),
         notifications_module.run(&config),
         sensors_module.run(&config),
+        session_lock_module.run(&config),
+        screensaver_inhibit_module.run(&config),
+        night_light_module.run(&config),
         custom_commands_module.run(&config),
     )?;
 
@@ -0,0 +1,113 @@
+use crate::config::BacklightCommandsConfig;
+use crate::modules::Backlight;
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use tokio::process::Command;
+
+const POWER_PLACEHOLDER: &str = "{power}";
+const BRIGHTNESS_PLACEHOLDER: &str = "{brightness}";
+
+/// Backlight controlled by user supplied shell commands, e.g. `ddcutil` or `brightnessctl`.
+///
+/// Values are remembered from the last set command, so get and set commands are optional.
+pub struct CommandBacklight {
+    commands: BacklightCommandsConfig,
+    power: AtomicBool,
+    brightness: AtomicU32,
+}
+
+impl CommandBacklight {
+    pub fn new(commands: BacklightCommandsConfig) -> Self {
+        let brightness = commands.max_brightness;
+        CommandBacklight {
+            commands,
+            power: AtomicBool::new(true),
+            brightness: AtomicU32::new(brightness),
+        }
+    }
+}
+
+impl Backlight for CommandBacklight {
+    fn set_brightness(&mut self, value: u32) -> BoxFuture<anyhow::Result<()>> {
+        async move {
+            if let Some(command) = self.commands.set_brightness.as_ref() {
+                run(&command.replace(BRIGHTNESS_PLACEHOLDER, &value.to_string())).await?;
+            }
+            self.brightness.store(value, Ordering::Relaxed);
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn get_brightness(&self) -> BoxFuture<anyhow::Result<u32>> {
+        async move {
+            match self.commands.get_brightness.as_ref() {
+                Some(command) => {
+                    let output = run(command).await?;
+
+                    Ok(output.parse()?)
+                }
+                None => Ok(self.brightness.load(Ordering::Relaxed)),
+            }
+        }
+        .boxed()
+    }
+
+    fn set_power(&mut self, value: bool) -> BoxFuture<anyhow::Result<()>> {
+        async move {
+            if let Some(command) = self.commands.set_power.as_ref() {
+                let power = if value { "on" } else { "off" };
+                run(&command.replace(POWER_PLACEHOLDER, power)).await?;
+            }
+            self.power.store(value, Ordering::Relaxed);
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn get_power(&self) -> BoxFuture<anyhow::Result<bool>> {
+        async move {
+            match self.commands.get_power.as_ref() {
+                Some(command) => {
+                    let output = run(command).await?;
+
+                    parse_power(&output)
+                }
+                None => Ok(self.power.load(Ordering::Relaxed)),
+            }
+        }
+        .boxed()
+    }
+
+    fn get_max_brightness(&self) -> BoxFuture<anyhow::Result<u32>> {
+        futures_util::future::ok(self.commands.max_brightness).boxed()
+    }
+}
+
+/// Runs the command with `sh -c` and returns its trimmed stdout.
+async fn run(command: &str) -> anyhow::Result<String> {
+    log::trace!("Running backlight command '{}'", command);
+    let output = Command::new("sh").arg("-c").arg(command).output().await?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Backlight command '{}' failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let stdout = String::from_utf8(output.stdout)?;
+
+    Ok(stdout.trim().to_string())
+}
+
+fn parse_power(output: &str) -> anyhow::Result<bool> {
+    match output.to_lowercase().as_str() {
+        "on" | "1" | "true" => Ok(true),
+        "off" | "0" | "false" => Ok(false),
+        output => anyhow::bail!("Unknown backlight power '{}'", output),
+    }
+}
@@ -1,62 +1,370 @@
-use crate::config::{BacklightProvider, Config};
+use crate::config::{
+    BacklightConfig, BacklightIdleAction, BacklightIdleConfig, BacklightProvider, Config,
+};
 use futures_util::future::{BoxFuture, LocalBoxFuture};
 use futures_util::FutureExt;
 use serde::Deserialize;
+use std::time::{Duration, Instant};
 use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc::UnboundedSender;
-use crate::core::state::{StateChange, PowerState};
+use crate::core::state::{DesktopState, StateChange, PowerState};
 use crate::core::mqtt::MqttMessage;
 use crate::core::LocalWorker;
+use crate::extensions::StringExt;
 
-mod raspberry_pi;
+mod command;
 mod stub;
+mod sysfs;
+
+pub const BACKLIGHT_COMPONENT: &str = "backlight";
+/// Brightness Home Assistant uses for full brightness, device brightness is scaled to this range
+pub const HASS_MAX_BRIGHTNESS: u32 = 255;
+/// Interval in which the brightness is updated during a transition
+const FADE_STEP: Duration = Duration::from_millis(50);
+
+/// Named backlight device with its own light entity and topics.
+#[derive(Debug, Clone)]
+pub struct BacklightDevice {
+    pub name: String,
+    pub id: String,
+}
+
+impl BacklightDevice {
+    /// Id of the device within discovery and availability.
+    ///
+    /// The default `Backlight` device keeps the id of the former single backlight entity.
+    pub fn object_id(&self) -> String {
+        Self::object_id_of(&self.id)
+    }
+
+    pub fn object_id_of(id: &str) -> String {
+        if id == BACKLIGHT_COMPONENT {
+            BACKLIGHT_COMPONENT.to_string()
+        } else {
+            format!("{}_{}", BACKLIGHT_COMPONENT, id)
+        }
+    }
+
+    pub fn state_topic(&self, entity_id: &str) -> String {
+        format!("desktop2mqtt/{}/backlight/{}", entity_id, self.id)
+    }
+
+    pub fn command_topic(&self, entity_id: &str) -> String {
+        format!("{}/set", self.state_topic(entity_id))
+    }
+
+    /// Command topic of the former single backlight entity, still accepted by the default device
+    pub fn legacy_command_topic(&self, entity_id: &str) -> Option<String> {
+        if self.id == BACKLIGHT_COMPONENT {
+            Some(format!("desktop2mqtt/{}/set", entity_id))
+        } else {
+            None
+        }
+    }
+}
+
+/// Runs the modules of all configured backlight devices.
+struct BacklightsModule {
+    modules: Vec<Box<dyn LocalWorker>>,
+}
+
+impl LocalWorker for BacklightsModule {
+    fn run(&mut self, config: &Config) -> LocalBoxFuture<anyhow::Result<()>> {
+        let modules = self.modules.iter_mut().map(|module| module.run(config));
+
+        futures_util::future::try_join_all(modules)
+            .map(|result| result.map(|_| ()))
+            .boxed_local()
+    }
+}
+
+struct BacklightChannels {
+    sender: UnboundedSender<StateChange>,
+    receiver: broadcast::Receiver<MqttMessage>,
+    state_updates: broadcast::Receiver<DesktopState>,
+}
 
 struct BacklightModule<T: Backlight> {
+    device: BacklightDevice,
     backlight: T,
     sender: UnboundedSender<StateChange>,
     receiver: broadcast::Receiver<MqttMessage>,
+    state_updates: broadcast::Receiver<DesktopState>,
+    min_brightness: u32,
+    poll_rate: Duration,
+    on_idle: Option<BacklightIdleConfig>,
 }
 
 impl<T: Backlight> BacklightModule<T> {
     fn new(
+        device: BacklightDevice,
         backlight: T,
-        sender: UnboundedSender<StateChange>,
-        receiver: broadcast::Receiver<MqttMessage>,
+        channels: BacklightChannels,
+        config: BacklightConfig,
     ) -> Self {
         BacklightModule {
+            device,
             backlight,
-            sender,
-            receiver,
+            sender: channels.sender,
+            receiver: channels.receiver,
+            state_updates: channels.state_updates,
+            min_brightness: config.min_brightness.unwrap_or_default(),
+            poll_rate: config.poll_rate,
+            on_idle: config.on_idle,
         }
     }
+
+    /// Reads power and brightness, the brightness is scaled to the range of Home Assistant
+    async fn read_state(&self) -> anyhow::Result<(bool, u32)> {
+        let power = self.backlight.get_power().await?;
+        let brightness = self.backlight.get_brightness().await?;
+        let max_brightness = self.backlight.get_max_brightness().await?;
+
+        Ok((power, from_device_brightness(brightness, max_brightness)))
+    }
+
+    /// Reads the current state and returns it when it differs from the last applied state.
+    ///
+    /// Brightness is compared in the range of the device, so rounding while scaling is not reported as a change.
+    async fn read_external_change(
+        &self,
+        power: bool,
+        brightness: u32,
+    ) -> anyhow::Result<Option<(bool, u32)>> {
+        let current_power = self.backlight.get_power().await?;
+        let current_brightness = self.backlight.get_brightness().await?;
+        let max_brightness = self.backlight.get_max_brightness().await?;
+        if current_power == power
+            && current_brightness == self.device_brightness(brightness, max_brightness)
+        {
+            return Ok(None);
+        }
+
+        Ok(Some((
+            current_power,
+            from_device_brightness(current_brightness, max_brightness),
+        )))
+    }
+
+    async fn apply(
+        &mut self,
+        update: &BacklightUpdate,
+        transition: Option<Duration>,
+        power: bool,
+        brightness: u32,
+    ) -> anyhow::Result<()> {
+        if update.power.is_some() {
+            self.backlight.set_power(power).await?;
+        }
+        // Brightness changes with a transition are applied by the fade instead
+        if update.brightness.is_some() && transition.is_none() {
+            self.write_brightness(brightness).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies power and brightness, the power is only written when it changes
+    async fn switch(
+        &mut self,
+        current_power: bool,
+        power: bool,
+        brightness: u32,
+    ) -> anyhow::Result<()> {
+        if power != current_power {
+            self.backlight.set_power(power).await?;
+        }
+
+        self.write_brightness(brightness).await
+    }
+
+    async fn write_brightness(&mut self, brightness: u32) -> anyhow::Result<()> {
+        let max_brightness = self.backlight.get_max_brightness().await?;
+        let brightness = self.device_brightness(brightness, max_brightness);
+
+        self.backlight.set_brightness(brightness).await
+    }
+
+    /// Brightness written to the device for the given brightness (0-255)
+    fn device_brightness(&self, brightness: u32, max_brightness: u32) -> u32 {
+        to_device_brightness(brightness, max_brightness)
+            .max(to_device_brightness(self.min_brightness, max_brightness))
+    }
+
+    fn send_state(&self, power: bool, brightness: u32) -> anyhow::Result<()> {
+        self.sender.send(StateChange::Backlight {
+            device: self.device.id.clone(),
+            power,
+            brightness,
+        })?;
+
+        Ok(())
+    }
+
+    fn set_available(&self, available: bool) -> anyhow::Result<()> {
+        self.sender.send(StateChange::Availability {
+            component: self.device.object_id(),
+            available,
+        })?;
+
+        Ok(())
+    }
 }
 
 impl<T: Backlight> LocalWorker for BacklightModule<T> {
     fn run(&mut self, config: &Config) -> LocalBoxFuture<anyhow::Result<()>> {
-        let topic = format!("desktop2mqtt/{}/set", config.hass.entity_id);
+        let topic = self.device.command_topic(&config.hass.entity_id);
+        let legacy_topic = self.device.legacy_command_topic(&config.hass.entity_id);
+        let mut idle_rule = self
+            .on_idle
+            .as_ref()
+            .and_then(|on_idle| IdleRule::resolve(on_idle, config));
         async move {
-            let mut power = self.backlight.get_power().await?;
-            let mut brightness = self.backlight.get_brightness().await?;
-
-            self.sender
-                .send(StateChange::Backlight { brightness, power })?;
-
-            while let Ok(msg) = self.receiver.recv().await {
-                if msg.topic != topic {
-                    continue;
+            let mut power = true;
+            let mut brightness = 0;
+            match self.read_state().await {
+                Ok((current_power, current_brightness)) => {
+                    power = current_power;
+                    brightness = current_brightness;
+                    self.set_available(true)?;
+                    self.send_state(power, brightness)?;
                 }
-                let state: BacklightUpdate = msg.deserialize()?;
-                if let Some(next) = state.power {
-                    power = next.into();
-                    self.backlight.set_power(power).await?;
-                }
-                if let Some(next) = state.brightness {
-                    brightness = next;
-                    self.backlight.set_brightness(brightness).await?;
+                Err(err) => {
+                    log::warn!("Unable to read backlight state: {:?}", err);
+                    self.set_available(false)?;
                 }
+            }
 
-                self.sender
-                    .send(StateChange::Backlight { brightness, power })?;
+            // Brightness last written to the backlight, differs from the reported brightness during a fade
+            let mut applied = brightness;
+            let mut fade: Option<Fade> = None;
+            // State to return to on activity while the idle rule is applied
+            let mut restore: Option<(bool, u32)> = None;
+            let mut poll_interval = tokio::time::interval(self.poll_rate);
+            // The first tick completes immediately, the state was just read
+            poll_interval.tick().await;
+            loop {
+                tokio::select! {
+                    msg = self.receiver.recv() => {
+                        let msg = match msg {
+                            Ok(msg) => msg,
+                            Err(RecvError::Lagged(_)) => continue,
+                            Err(RecvError::Closed) => break,
+                        };
+                        if msg.topic != topic && Some(&msg.topic) != legacy_topic.as_ref() {
+                            continue;
+                        }
+                        // Every command cancels a running fade and overrides the idle rule
+                        if fade.take().is_some() {
+                            // The backlight stays at the brightness the fade reached so far
+                            brightness = applied;
+                        }
+                        restore = None;
+                        let state: BacklightUpdate = msg.deserialize()?;
+                        let transition = state.transition();
+                        if let Some(next) = state.power {
+                            power = next.into();
+                        }
+                        if let Some(next) = state.brightness {
+                            brightness = next.clamp(self.min_brightness, HASS_MAX_BRIGHTNESS);
+                        }
+                        match self.apply(&state, transition, power, brightness).await {
+                            Ok(()) => self.set_available(true)?,
+                            Err(err) => {
+                                log::warn!("Unable to update backlight: {:?}", err);
+                                self.set_available(false)?;
+                            }
+                        }
+                        if state.brightness.is_some() {
+                            match transition {
+                                Some(transition) => {
+                                    fade = Some(Fade::new(applied, brightness, transition));
+                                }
+                                None => applied = brightness,
+                            }
+                        }
+
+                        self.send_state(power, brightness)?;
+                    }
+                    _ = tokio::time::sleep(FADE_STEP), if fade.is_some() => {
+                        let (next, done) = match fade.as_ref() {
+                            Some(current) => current.step(),
+                            None => continue,
+                        };
+                        if let Err(err) = self.write_brightness(next).await {
+                            log::warn!("Unable to fade backlight: {:?}", err);
+                            self.set_available(false)?;
+                            fade = None;
+                            continue;
+                        }
+                        applied = next;
+                        if done {
+                            fade = None;
+                        }
+                    }
+                    update = self.state_updates.recv(), if idle_rule.is_some() => {
+                        let level = match update {
+                            Ok(update) => update.idle_level,
+                            Err(RecvError::Lagged(_)) => continue,
+                            Err(RecvError::Closed) => {
+                                idle_rule = None;
+                                continue;
+                            }
+                        };
+                        let (rule, level) = match (idle_rule.as_ref(), level) {
+                            (Some(rule), Some(level)) => (rule, level),
+                            _ => continue,
+                        };
+                        let reached = rule.is_reached(&level);
+                        let (next_power, next_brightness) = match (reached, restore) {
+                            (true, None) => {
+                                restore = Some((power, brightness));
+                                rule.apply(power, brightness)
+                            }
+                            (false, Some(previous)) => {
+                                restore = None;
+                                previous
+                            }
+                            _ => continue,
+                        };
+                        fade = None;
+                        log::debug!(
+                            "Switching backlight {} for idle level {}",
+                            self.device.name,
+                            level
+                        );
+                        match self.switch(power, next_power, next_brightness).await {
+                            Ok(()) => self.set_available(true)?,
+                            Err(err) => {
+                                log::warn!("Unable to update backlight: {:?}", err);
+                                self.set_available(false)?;
+                            }
+                        }
+                        power = next_power;
+                        brightness = next_brightness;
+                        applied = next_brightness;
+                        self.send_state(power, brightness)?;
+                    }
+                    // Polling during a fade would report the intermediate brightness as external change
+                    _ = poll_interval.tick(), if fade.is_none() => {
+                        match self.read_external_change(power, applied).await {
+                            Ok(Some((current_power, current_brightness))) => {
+                                log::debug!("Backlight {} was changed externally", self.device.name);
+                                power = current_power;
+                                brightness = current_brightness;
+                                applied = current_brightness;
+                                self.set_available(true)?;
+                                self.send_state(power, brightness)?;
+                            }
+                            Ok(None) => self.set_available(true)?,
+                            Err(err) => {
+                                log::warn!("Unable to read backlight state: {:?}", err);
+                                self.set_available(false)?;
+                            }
+                        }
+                    }
+                }
             }
 
             Ok(())
@@ -70,29 +378,98 @@ pub trait Backlight: Send {
     fn get_brightness(&self) -> BoxFuture<anyhow::Result<u32>>;
     fn set_power(&mut self, value: bool) -> BoxFuture<anyhow::Result<()>>;
     fn get_power(&self) -> BoxFuture<anyhow::Result<bool>>;
+    fn get_max_brightness(&self) -> BoxFuture<anyhow::Result<u32>>;
+}
+
+/// Scales a Home Assistant brightness to the range of the device.
+///
+/// Rounds up so a non zero brightness never turns the backlight fully dark.
+fn to_device_brightness(brightness: u32, max_brightness: u32) -> u32 {
+    let brightness = brightness.min(HASS_MAX_BRIGHTNESS) as u64;
+    let max = HASS_MAX_BRIGHTNESS as u64;
+
+    (brightness * max_brightness as u64).div_ceil(max) as u32
+}
+
+fn from_device_brightness(brightness: u32, max_brightness: u32) -> u32 {
+    if max_brightness == 0 {
+        return 0;
+    }
+    let brightness = brightness.min(max_brightness) as u64;
+    let max_brightness = max_brightness as u64;
+
+    ((brightness * HASS_MAX_BRIGHTNESS as u64 + max_brightness / 2) / max_brightness) as u32
+}
+
+pub fn get_backlight_devices(configs: &[BacklightConfig]) -> Vec<BacklightDevice> {
+    configs
+        .iter()
+        .map(|config| BacklightDevice {
+            name: config.name.clone(),
+            id: config.name.to_slug(),
+        })
+        .collect()
 }
 
 pub fn get_backlight_module(
     sender: UnboundedSender<StateChange>,
-    receiver: broadcast::Receiver<MqttMessage>,
-    config: BacklightProvider,
+    mqtt_events: &broadcast::Sender<MqttMessage>,
+    state_updates: &broadcast::Sender<DesktopState>,
+    configs: &[BacklightConfig],
 ) -> Box<dyn LocalWorker> {
-    match config {
-        BacklightProvider::RaspberryPi => to_module(
-            self::raspberry_pi::RaspberryPiBacklight::new(),
-            sender,
-            receiver,
-        ),
-        BacklightProvider::Stub => to_module(self::stub::StubBacklight::new(), sender, receiver),
+    let modules = configs
+        .iter()
+        .cloned()
+        .zip(get_backlight_devices(configs))
+        .map(|(config, device)| {
+            let channels = BacklightChannels {
+                sender: sender.clone(),
+                receiver: mqtt_events.subscribe(),
+                state_updates: state_updates.subscribe(),
+            };
+            get_device_module(device, channels, config)
+        })
+        .collect();
+
+    Box::new(BacklightsModule { modules })
+}
+
+fn get_device_module(
+    device: BacklightDevice,
+    channels: BacklightChannels,
+    config: BacklightConfig,
+) -> Box<dyn LocalWorker> {
+    match config.provider {
+        BacklightProvider::RaspberryPi => {
+            let backlight = self::sysfs::SysfsBacklight::raspberry_pi(config.sysfs_root.clone());
+            to_module(device, backlight, channels, config)
+        }
+        BacklightProvider::Stub => {
+            to_module(device, self::stub::StubBacklight::new(), channels, config)
+        }
+        BacklightProvider::Sysfs => {
+            let backlight =
+                self::sysfs::SysfsBacklight::new(config.sysfs_root.clone(), config.device.clone());
+            to_module(device, backlight, channels, config)
+        }
+        BacklightProvider::Command => {
+            let commands = config
+                .commands
+                .clone()
+                .expect("command provider is validated to have commands");
+            let backlight = self::command::CommandBacklight::new(commands);
+            to_module(device, backlight, channels, config)
+        }
     }
 }
 
 fn to_module<TBacklight: Backlight + 'static>(
+    device: BacklightDevice,
     backlight: TBacklight,
-    sender: UnboundedSender<StateChange>,
-    receiver: broadcast::Receiver<MqttMessage>,
+    channels: BacklightChannels,
+    config: BacklightConfig,
 ) -> Box<dyn LocalWorker> {
-    let module = BacklightModule::new(backlight, sender, receiver);
+    let module = BacklightModule::new(device, backlight, channels, config);
 
     Box::new(module)
 }
@@ -102,4 +479,102 @@ pub struct BacklightUpdate {
     #[serde(rename = "state")]
     pub power: Option<PowerState>,
     pub brightness: Option<u32>,
+    /// Seconds to fade the brightness over
+    pub transition: Option<f32>,
+}
+
+impl BacklightUpdate {
+    fn transition(&self) -> Option<Duration> {
+        let transition = self.transition.filter(|transition| *transition > 0.0)?;
+        match Duration::try_from_secs_f32(transition) {
+            Ok(transition) => Some(transition),
+            Err(err) => {
+                log::warn!("Ignoring invalid transition {}: {}", transition, err);
+                None
+            }
+        }
+    }
+}
+
+/// Idle rule of a backlight resolved against the configured idle levels.
+struct IdleRule {
+    action: BacklightIdleAction,
+    brightness: u32,
+    /// Idle levels at which the rule applies
+    levels: Vec<String>,
+}
+
+impl IdleRule {
+    fn resolve(on_idle: &BacklightIdleConfig, config: &Config) -> Option<Self> {
+        let idle = match config.modules.idle.as_ref() {
+            Some(idle) => idle,
+            None => {
+                log::error!("The backlight idle rule requires the idle module");
+                return None;
+            }
+        };
+        let levels = idle.levels().ok()?;
+        let position = match levels.iter().position(|level| level.name == on_idle.level) {
+            Some(position) => position,
+            None => {
+                log::error!(
+                    "Unknown idle level {} in backlight idle rule",
+                    on_idle.level
+                );
+                return None;
+            }
+        };
+
+        Some(IdleRule {
+            action: on_idle.action,
+            brightness: on_idle.brightness,
+            levels: levels[position..]
+                .iter()
+                .map(|level| level.name.clone())
+                .collect(),
+        })
+    }
+
+    fn is_reached(&self, level: &str) -> bool {
+        self.levels.iter().any(|candidate| candidate == level)
+    }
+
+    /// Power and brightness while the rule applies
+    fn apply(&self, power: bool, brightness: u32) -> (bool, u32) {
+        match self.action {
+            BacklightIdleAction::Off => (false, brightness),
+            BacklightIdleAction::Dim => (power, brightness.min(self.brightness)),
+        }
+    }
+}
+
+/// Linear brightness fade from one brightness (0-255) to another.
+struct Fade {
+    from: u32,
+    to: u32,
+    started: Instant,
+    duration: Duration,
+}
+
+impl Fade {
+    fn new(from: u32, to: u32, duration: Duration) -> Self {
+        Fade {
+            from,
+            to,
+            started: Instant::now(),
+            duration,
+        }
+    }
+
+    /// Brightness for the current point in time and whether the fade is finished.
+    fn step(&self) -> (u32, bool) {
+        let progress = self.started.elapsed().as_secs_f32() / self.duration.as_secs_f32();
+        if progress >= 1.0 {
+            return (self.to, true);
+        }
+        let delta = self.to as f32 - self.from as f32;
+        let brightness = (self.from as f32 + delta * progress).round() as u32;
+
+        (brightness, false)
+    }
 }
@@ -1,4 +1,4 @@
-use super::Backlight;
+use super::{Backlight, HASS_MAX_BRIGHTNESS};
 use futures_util::future::BoxFuture;
 use futures_util::FutureExt;
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
@@ -31,7 +31,7 @@ impl Backlight for StubBacklight {
 
     fn get_brightness(&self) -> BoxFuture<anyhow::Result<u32>> {
         async move {
-            let brightness = self.brightness.fetch_or(255, Ordering::Relaxed);
+            let brightness = self.brightness.load(Ordering::Relaxed);
 
             Ok(brightness)
         }
@@ -50,10 +50,14 @@ impl Backlight for StubBacklight {
 
     fn get_power(&self) -> BoxFuture<anyhow::Result<bool>> {
         async move {
-            let power = self.power.fetch_or(true, Ordering::Relaxed);
+            let power = self.power.load(Ordering::Relaxed);
 
             Ok(power)
         }
         .boxed()
     }
+
+    fn get_max_brightness(&self) -> BoxFuture<anyhow::Result<u32>> {
+        futures_util::future::ok(HASS_MAX_BRIGHTNESS).boxed()
+    }
 }
@@ -0,0 +1,220 @@
+use crate::modules::Backlight;
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::OnceCell;
+
+const POWER: &str = "bl_power";
+const BRIGHTNESS: &str = "brightness";
+const ACTUAL_BRIGHTNESS: &str = "actual_brightness";
+const MAX_BRIGHTNESS: &str = "max_brightness";
+const TYPE: &str = "type";
+/// Device of the official Raspberry Pi touch display
+const RASPBERRY_PI_DEVICE: &str = "rpi_backlight";
+
+/// `FB_BLANK_UNBLANK`, every other value of `bl_power` turns the backlight off
+const POWER_ON: u32 = 0;
+/// `FB_BLANK_POWERDOWN`
+const POWER_OFF: u32 = 4;
+
+/// Backlight exposed by the kernel in `/sys/class/backlight`.
+///
+/// Without a configured device the best device in the sysfs root is used.
+pub struct SysfsBacklight {
+    root: PathBuf,
+    device: Option<String>,
+    /// Path of the device, resolved on first access
+    path: OnceCell<PathBuf>,
+}
+
+impl SysfsBacklight {
+    pub fn new(root: PathBuf, device: Option<String>) -> Self {
+        SysfsBacklight {
+            root,
+            device,
+            path: OnceCell::new(),
+        }
+    }
+
+    pub fn raspberry_pi(root: PathBuf) -> Self {
+        Self::new(root, Some(RASPBERRY_PI_DEVICE.to_string()))
+    }
+}
+
+impl Backlight for SysfsBacklight {
+    fn set_brightness(&mut self, value: u32) -> BoxFuture<anyhow::Result<()>> {
+        self.write_brightness(value).boxed()
+    }
+
+    fn get_brightness(&self) -> BoxFuture<anyhow::Result<u32>> {
+        self.read_value(ACTUAL_BRIGHTNESS).boxed()
+    }
+
+    fn set_power(&mut self, value: bool) -> BoxFuture<anyhow::Result<()>> {
+        let power = if value { POWER_ON } else { POWER_OFF };
+
+        self.write_value(POWER, power).boxed()
+    }
+
+    fn get_power(&self) -> BoxFuture<anyhow::Result<bool>> {
+        async move {
+            let power = self.read_value(POWER).await?;
+
+            Ok(power == POWER_ON)
+        }
+        .boxed()
+    }
+
+    fn get_max_brightness(&self) -> BoxFuture<anyhow::Result<u32>> {
+        self.read_value(MAX_BRIGHTNESS).boxed()
+    }
+}
+
+impl SysfsBacklight {
+    async fn write_brightness(&self, brightness: u32) -> anyhow::Result<()> {
+        let max_brightness = self.read_value(MAX_BRIGHTNESS).await?;
+
+        self.write_value(BRIGHTNESS, brightness.min(max_brightness))
+            .await
+    }
+
+    async fn device_path(&self) -> anyhow::Result<&PathBuf> {
+        self.path
+            .get_or_try_init(|| async {
+                match self.device.as_ref() {
+                    Some(device) => Ok(self.root.join(device)),
+                    None => Self::detect_device(&self.root).await,
+                }
+            })
+            .await
+    }
+
+    /// Picks the device the kernel considers most suitable, like systemd-backlight does.
+    async fn detect_device(root: &Path) -> anyhow::Result<PathBuf> {
+        let mut entries = tokio::fs::read_dir(root).await?;
+        let mut devices = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let device_type = tokio::fs::read_to_string(path.join(TYPE))
+                .await
+                .unwrap_or_default();
+            devices.push((type_priority(device_type.trim()), path));
+        }
+        // read_dir is unordered, sorting by name as well keeps the detected device stable across restarts
+        devices.sort();
+        let (_, device) = devices
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("No backlight device found in {:?}", root))?;
+        log::trace!("Detected backlight device {:?}", device);
+
+        Ok(device)
+    }
+
+    async fn read_value(&self, name: &str) -> anyhow::Result<u32> {
+        let mut file = self.open_file(name, OpenOptions::new().read(true)).await?;
+        let mut content = String::new();
+        file.read_to_string(&mut content).await?;
+        log::trace!("Read {} from {}", &content, name);
+        let value = content.trim().parse()?;
+
+        Ok(value)
+    }
+
+    async fn write_value(&self, name: &str, value: u32) -> anyhow::Result<()> {
+        let mut file = self
+            .open_file(name, OpenOptions::new().write(true).truncate(true))
+            .await?;
+        let content = value.to_string();
+        log::trace!("Writing {} to {}", &content, name);
+        file.write_all(content.as_bytes()).await?;
+        // tokio writes in the background, flushing waits for the kernel to accept the value
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    async fn open_file(&self, name: &str, options: &mut OpenOptions) -> anyhow::Result<File> {
+        let file_path = self.device_path().await?.join(name);
+        log::trace!("Opening file {:?}...", &file_path);
+        let file = options.open(&file_path).await?;
+
+        Ok(file)
+    }
+}
+
+/// Sort key of a backlight type, directly controlling the hardware is preferred over firmware interfaces.
+fn type_priority(device_type: &str) -> u8 {
+    match device_type {
+        "raw" => 0,
+        "platform" => 1,
+        "firmware" => 2,
+        _ => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Creates a fake `/sys/class/backlight` with a firmware and a raw device.
+    fn fake_sysfs(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("desktop2mqtt-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (device, device_type) in &[("acpi_video0", "firmware"), ("intel_backlight", "raw")] {
+            let path = root.join(device);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join(TYPE), format!("{}\n", device_type)).unwrap();
+            fs::write(path.join(POWER), "0\n").unwrap();
+            fs::write(path.join(BRIGHTNESS), "100\n").unwrap();
+            fs::write(path.join(ACTUAL_BRIGHTNESS), "100\n").unwrap();
+            fs::write(path.join(MAX_BRIGHTNESS), "200\n").unwrap();
+        }
+
+        root
+    }
+
+    fn read(root: &Path, device: &str, name: &str) -> String {
+        fs::read_to_string(root.join(device).join(name)).unwrap()
+    }
+
+    #[test]
+    fn prefers_raw_devices() {
+        let root = fake_sysfs("sysfs-detect");
+        let mut backlight = SysfsBacklight::new(root.clone(), None);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        runtime.block_on(async {
+            assert_eq!(backlight.get_max_brightness().await.unwrap(), 200);
+            assert!(backlight.get_power().await.unwrap());
+            backlight.set_brightness(50).await.unwrap();
+            backlight.set_brightness(500).await.unwrap();
+            backlight.set_power(false).await.unwrap();
+        });
+
+        assert_eq!(read(&root, "intel_backlight", BRIGHTNESS), "200");
+        assert_eq!(read(&root, "intel_backlight", POWER), "4");
+        assert_eq!(read(&root, "acpi_video0", BRIGHTNESS), "100\n");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn uses_configured_device() {
+        let root = fake_sysfs("sysfs-configured");
+        let mut backlight = SysfsBacklight::new(root.clone(), Some("acpi_video0".to_string()));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        runtime.block_on(async {
+            backlight.set_brightness(42).await.unwrap();
+            assert_eq!(backlight.get_brightness().await.unwrap(), 100);
+        });
+
+        assert_eq!(read(&root, "acpi_video0", BRIGHTNESS), "42");
+        assert_eq!(read(&root, "intel_backlight", BRIGHTNESS), "100\n");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
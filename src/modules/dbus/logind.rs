@@ -0,0 +1,253 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedSender;
+use zbus::dbus_proxy;
+use zbus::export::zvariant::OwnedObjectPath;
+use zbus::fdo::PropertiesProxy;
+use zbus::Connection;
+
+const LOGIND_SERVICE: &str = "org.freedesktop.login1";
+/// Object path logind resolves to the session of the calling process.
+const AUTO_SESSION_PATH: &str = "/org/freedesktop/login1/session/auto";
+
+/// What, who, why, mode, uid and pid of an inhibitor lock
+type Inhibitor = (String, String, String, String, u32, u32);
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Manager {
+    fn get_session(&self, session_id: &str) -> zbus::Result<OwnedObjectPath>;
+
+    fn list_inhibitors(&self) -> zbus::Result<Vec<Inhibitor>>;
+}
+
+/// Checks whether any application currently blocks the session from going idle.
+pub fn has_idle_inhibitor(connection: &Connection) -> anyhow::Result<bool> {
+    let manager = ManagerProxy::new(connection)?;
+    let inhibitors = manager.list_inhibitors()?;
+
+    Ok(inhibitors.iter().any(|(what, who, _, mode, _, _)| {
+        let idle = mode == "block" && what.split(':').any(|what| what == "idle");
+        if idle {
+            log::trace!("Idle is inhibited by {}", who);
+        }
+        idle
+    }))
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1"
+)]
+trait Session {
+    fn lock(&self) -> zbus::Result<()>;
+
+    fn unlock(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(property)]
+    fn id(&self) -> zbus::fdo::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn locked_hint(&self) -> zbus::fdo::Result<bool>;
+
+    #[dbus_proxy(property)]
+    fn idle_hint(&self) -> zbus::fdo::Result<bool>;
+
+    /// Microseconds since the epoch the idle hint was last changed
+    #[dbus_proxy(property)]
+    fn idle_since_hint(&self) -> zbus::fdo::Result<u64>;
+}
+
+/// Idle state of a session as reported by the desktop environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdleHint {
+    pub idle: bool,
+    pub since: SystemTime,
+}
+
+impl IdleHint {
+    /// Time since the user was last active.
+    pub fn idle_time(&self) -> Duration {
+        if self.idle {
+            SystemTime::now()
+                .duration_since(self.since)
+                .unwrap_or_default()
+        } else {
+            Duration::default()
+        }
+    }
+}
+
+/// A logind session, either the one of this process or a specific one by id.
+#[derive(Clone)]
+pub struct LogindSession {
+    connection: Connection,
+    path: String,
+}
+
+impl LogindSession {
+    pub fn new(connection: Connection, session_id: Option<&str>) -> anyhow::Result<Self> {
+        // Signals are emitted for the actual session path, so the auto path is resolved as well
+        let session_id = match session_id {
+            Some(session_id) => session_id.to_string(),
+            None => SessionProxy::new_for_path(&connection, AUTO_SESSION_PATH)?.id()?,
+        };
+        let manager = ManagerProxy::new(&connection)?;
+        let path = manager.get_session(&session_id)?.as_str().to_string();
+        log::debug!("Using logind session {}", &path);
+
+        Ok(LogindSession { connection, path })
+    }
+
+    fn proxy(&self) -> anyhow::Result<SessionProxy<'_>> {
+        let proxy = SessionProxy::new_for_path(&self.connection, &self.path)?;
+
+        Ok(proxy)
+    }
+
+    pub fn is_locked(&self) -> anyhow::Result<bool> {
+        let locked = self.proxy()?.locked_hint()?;
+
+        Ok(locked)
+    }
+
+    pub fn lock(&self) -> anyhow::Result<()> {
+        self.proxy()?.lock()?;
+
+        Ok(())
+    }
+
+    pub fn unlock(&self) -> anyhow::Result<()> {
+        self.proxy()?.unlock()?;
+
+        Ok(())
+    }
+
+    pub fn idle_hint(&self) -> anyhow::Result<IdleHint> {
+        let proxy = self.proxy()?;
+        let idle = proxy.idle_hint()?;
+        let since = UNIX_EPOCH + Duration::from_micros(proxy.idle_since_hint()?);
+
+        Ok(IdleHint { idle, since })
+    }
+
+    /// Blocks the current thread and sends the idle hint every time the session properties change.
+    pub fn watch_idle_hint(&self, sender: UnboundedSender<IdleHint>) -> anyhow::Result<()> {
+        let properties = PropertiesProxy::new_for(&self.connection, LOGIND_SERVICE, &self.path)?;
+        properties.connect_properties_changed(|interface, _, _| {
+            log::trace!("Properties of {} changed", interface);
+            Ok(())
+        })?;
+        loop {
+            properties.next_signal()?;
+            let hint = self.idle_hint()?;
+            if sender.send(hint).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::dbus::test_bus::PrivateBus;
+    use std::convert::TryFrom;
+    use std::sync::mpsc;
+    use zbus::export::zvariant::ObjectPath;
+    use zbus::{dbus_interface, fdo, ObjectServer};
+
+    const SESSION_ID: &str = "c1";
+    const SESSION_PATH: &str = "/org/freedesktop/login1/session/c1";
+
+    struct MockManager;
+
+    #[dbus_interface(name = "org.freedesktop.login1.Manager")]
+    impl MockManager {
+        fn get_session(&self, session_id: &str) -> fdo::Result<OwnedObjectPath> {
+            if session_id != SESSION_ID {
+                return Err(fdo::Error::Failed(format!("No session {}", session_id)));
+            }
+
+            Ok(ObjectPath::try_from(SESSION_PATH).unwrap().into())
+        }
+    }
+
+    /// Session which locks itself instead of asking a screen locker to do it
+    struct MockSession {
+        locked: bool,
+    }
+
+    #[dbus_interface(name = "org.freedesktop.login1.Session")]
+    impl MockSession {
+        fn lock(&mut self) {
+            self.locked = true;
+        }
+
+        fn unlock(&mut self) {
+            self.locked = false;
+        }
+
+        #[dbus_interface(property)]
+        fn locked_hint(&self) -> bool {
+            self.locked
+        }
+    }
+
+    /// Serves a mock logind on the given bus until the bus is gone.
+    fn serve_logind(bus: &PrivateBus) {
+        let connection = bus.connect();
+        let (ready_sender, ready) = mpsc::channel();
+        std::thread::spawn(move || {
+            fdo::DBusProxy::new(&connection)
+                .unwrap()
+                .request_name(LOGIND_SERVICE, fdo::RequestNameFlags::DoNotQueue.into())
+                .unwrap();
+            let mut server = ObjectServer::new(&connection);
+            server
+                .at(
+                    &ObjectPath::try_from("/org/freedesktop/login1").unwrap(),
+                    MockManager,
+                )
+                .unwrap();
+            server
+                .at(
+                    &ObjectPath::try_from(SESSION_PATH).unwrap(),
+                    MockSession { locked: false },
+                )
+                .unwrap();
+            ready_sender.send(()).unwrap();
+            while server.try_handle_next().is_ok() {}
+        });
+        ready.recv().unwrap();
+    }
+
+    #[test]
+    fn locks_and_unlocks_session() {
+        let bus = match PrivateBus::start() {
+            Some(bus) => bus,
+            None => return,
+        };
+        serve_logind(&bus);
+
+        let session = LogindSession::new(bus.connect(), Some(SESSION_ID)).unwrap();
+        assert!(!session.is_locked().unwrap());
+        session.lock().unwrap();
+        assert!(session.is_locked().unwrap());
+        session.unlock().unwrap();
+        assert!(!session.is_locked().unwrap());
+    }
+
+    #[test]
+    fn fails_for_unknown_session() {
+        let bus = match PrivateBus::start() {
+            Some(bus) => bus,
+            None => return,
+        };
+        serve_logind(&bus);
+
+        assert!(LogindSession::new(bus.connect(), Some("unknown")).is_err());
+    }
+}
@@ -0,0 +1,30 @@
+use crate::config::DbusBus;
+use zbus::Connection;
+
+pub mod logind;
+pub mod mpris;
+pub mod notifications;
+pub mod screensaver;
+#[cfg(test)]
+pub mod test_bus;
+
+/// Opens a connection to the given message bus.
+///
+/// zbus connections are blocking, so this should be called from a blocking task.
+pub fn connect(bus: DbusBus) -> anyhow::Result<Connection> {
+    let connection = match bus {
+        DbusBus::System => Connection::new_system()?,
+        DbusBus::Session => Connection::new_session()?,
+    };
+
+    Ok(connection)
+}
+
+/// Runs a blocking D-Bus call on the blocking thread pool of tokio.
+pub async fn blocking<T, F>(call: F) -> anyhow::Result<T>
+where
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(call).await?
+}
@@ -0,0 +1,33 @@
+use zbus::dbus_proxy;
+use zbus::fdo::DBusProxy;
+use zbus::Connection;
+
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYING: &str = "Playing";
+
+#[dbus_proxy(interface = "org.mpris.MediaPlayer2.Player")]
+trait Player {
+    #[dbus_proxy(property)]
+    fn playback_status(&self) -> zbus::fdo::Result<String>;
+}
+
+/// Checks whether any MPRIS media player on the bus is currently playing.
+///
+/// Players which fail to report their status are treated as not playing.
+pub fn is_playing(connection: &Connection) -> anyhow::Result<bool> {
+    let names = DBusProxy::new(connection)?.list_names()?;
+    for name in names.iter().filter(|name| name.starts_with(MPRIS_PREFIX)) {
+        let player = PlayerProxy::new_for(connection, name, MPRIS_PATH)?;
+        match player.playback_status() {
+            Ok(status) if status == PLAYING => {
+                log::trace!("{} is playing", name);
+                return Ok(true);
+            }
+            Ok(_) => {}
+            Err(err) => log::debug!("Unable to read playback status of {}: {:?}", name, err),
+        }
+    }
+
+    Ok(false)
+}
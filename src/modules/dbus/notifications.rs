@@ -0,0 +1,20 @@
+use zbus::dbus_proxy;
+use zbus::Connection;
+
+#[dbus_proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    fn close_notification(&self, id: u32) -> zbus::Result<()>;
+}
+
+/// Closes a notification shown by any connection, e.g. one notify-rust has shown.
+pub fn close_notification(connection: &Connection, id: u32) -> anyhow::Result<()> {
+    let proxy = NotificationsProxy::new(connection)?;
+    proxy.close_notification(id)?;
+    log::debug!("Closed notification {}", id);
+
+    Ok(())
+}
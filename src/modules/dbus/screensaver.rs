@@ -0,0 +1,64 @@
+use zbus::dbus_proxy;
+use zbus::Connection;
+
+#[dbus_proxy(
+    interface = "org.freedesktop.ScreenSaver",
+    default_service = "org.freedesktop.ScreenSaver",
+    default_path = "/org/freedesktop/ScreenSaver"
+)]
+trait ScreenSaver {
+    fn inhibit(&self, application_name: &str, reason_for_inhibit: &str) -> zbus::Result<u32>;
+
+    fn un_inhibit(&self, cookie: u32) -> zbus::Result<()>;
+}
+
+/// Holds a screen saver inhibit cookie as long as it is active.
+///
+/// Screen savers drop inhibits of closed connections, so the connection is kept open alongside the cookie.
+pub struct ScreenSaverInhibitor {
+    connection: Connection,
+    cookie: Option<u32>,
+}
+
+impl ScreenSaverInhibitor {
+    pub fn new(connection: Connection) -> Self {
+        ScreenSaverInhibitor {
+            connection,
+            cookie: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.cookie.is_some()
+    }
+
+    pub fn inhibit(&mut self, reason: &str) -> anyhow::Result<()> {
+        if self.cookie.is_some() {
+            return Ok(());
+        }
+        let proxy = ScreenSaverProxy::new(&self.connection)?;
+        let cookie = proxy.inhibit(env!("CARGO_PKG_NAME"), reason)?;
+        log::debug!("Inhibited screen saver with cookie {}", cookie);
+        self.cookie = Some(cookie);
+
+        Ok(())
+    }
+
+    pub fn release(&mut self) -> anyhow::Result<()> {
+        if let Some(cookie) = self.cookie.take() {
+            let proxy = ScreenSaverProxy::new(&self.connection)?;
+            proxy.un_inhibit(cookie)?;
+            log::debug!("Released screen saver inhibit {}", cookie);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ScreenSaverInhibitor {
+    fn drop(&mut self) {
+        if let Err(err) = self.release() {
+            log::warn!("Unable to release screen saver inhibit: {:?}", err);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use zbus::Connection;
+
+/// Private session bus for tests, stopped when dropped.
+pub struct PrivateBus {
+    daemon: Child,
+    address: String,
+}
+
+impl PrivateBus {
+    /// Starts a `dbus-daemon`, returns `None` when it is not installed.
+    pub fn start() -> Option<Self> {
+        let mut daemon = match Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(err) => {
+                eprintln!("Skipping test without dbus-daemon: {}", err);
+                return None;
+            }
+        };
+        let mut address = String::new();
+        let stdout = daemon.stdout.take().unwrap();
+        BufReader::new(stdout).read_line(&mut address).unwrap();
+
+        Some(PrivateBus {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+
+    pub fn connect(&self) -> Connection {
+        Connection::new_for_address(&self.address, true).unwrap()
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
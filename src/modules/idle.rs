@@ -1,35 +1,248 @@
-use crate::config::Config;
+use crate::config::{
+    Config, IdleBackend, IdleLevelConfig, IdleModuleConfig, ACTIVE_IDLE_LEVEL,
+};
 use futures_util::future::BoxFuture;
 use futures_util::FutureExt;
-use tokio::sync::mpsc::UnboundedSender;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use user_idle::UserIdle;
 use crate::core::state::StateChange;
 use crate::core::Worker;
+use crate::config::DbusBus;
+use crate::modules::dbus::{self, logind, logind::LogindSession, mpris};
+use zbus::Connection;
+
+pub const IDLE_COMPONENT: &str = "idle";
+/// Idle times are only reported in whole seconds
+const IDLE_TIME_PRECISION: Duration = Duration::from_secs(1);
 
 pub struct IdleModule {
     sender: UnboundedSender<StateChange>,
 }
 
+/// Sources besides user input which keep the device occupied.
+#[derive(Clone, Default)]
+struct ActivitySources {
+    media: Option<Connection>,
+    inhibitors: Option<Connection>,
+}
+
+impl ActivitySources {
+    async fn connect(config: &IdleModuleConfig) -> Self {
+        let media = config.media_playback;
+        let inhibitors = config.idle_inhibitors;
+        let bus = config.bus;
+        let sources = dbus::blocking(move || {
+            Ok(ActivitySources {
+                media: if media {
+                    Self::connect_bus(DbusBus::Session, "media players")
+                } else {
+                    None
+                },
+                inhibitors: if inhibitors {
+                    Self::connect_bus(bus, "logind")
+                } else {
+                    None
+                },
+            })
+        })
+        .await;
+
+        sources.unwrap_or_default()
+    }
+
+    fn connect_bus(bus: DbusBus, target: &str) -> Option<Connection> {
+        match dbus::connect(bus) {
+            Ok(connection) => Some(connection),
+            Err(err) => {
+                log::error!("Unable to connect to {}: {:?}", target, err);
+                None
+            }
+        }
+    }
+
+    async fn is_active(&self) -> bool {
+        if self.media.is_none() && self.inhibitors.is_none() {
+            return false;
+        }
+        let sources = self.clone();
+        let active = dbus::blocking(move || {
+            if let Some(connection) = sources.media.as_ref() {
+                match mpris::is_playing(connection) {
+                    Ok(true) => return Ok(true),
+                    Ok(false) => {}
+                    Err(err) => log::warn!("Unable to read media players: {:?}", err),
+                }
+            }
+            if let Some(connection) = sources.inhibitors.as_ref() {
+                match logind::has_idle_inhibitor(connection) {
+                    Ok(true) => return Ok(true),
+                    Ok(false) => {}
+                    Err(err) => log::warn!("Unable to read idle inhibitors: {:?}", err),
+                }
+            }
+            Ok(false)
+        })
+        .await;
+
+        active.unwrap_or(false)
+    }
+}
+
 impl IdleModule {
     pub fn new(sender: UnboundedSender<StateChange>) -> Self {
         IdleModule { sender }
     }
+
+    fn set_available(&self, available: bool) -> anyhow::Result<()> {
+        self.sender.send(StateChange::Availability {
+            component: IDLE_COMPONENT.to_string(),
+            available,
+        })?;
+
+        Ok(())
+    }
+
+    async fn report(
+        &self,
+        idle_time: Duration,
+        levels: &[IdleLevelConfig],
+        occupancy_timeout: Duration,
+        last_activity: &mut Option<SystemTime>,
+        sources: &ActivitySources,
+    ) -> anyhow::Result<()> {
+        self.sender.send(StateChange::IdleTime(idle_time))?;
+        // Only report a new activity timestamp when it moved by more than the precision
+        // of the idle time to prevent it from jittering between reports.
+        let activity = SystemTime::now() - idle_time;
+        let moved = last_activity
+            .map(|last_activity| activity > last_activity + IDLE_TIME_PRECISION)
+            .unwrap_or(true);
+        if moved {
+            self.sender.send(StateChange::LastActivity(activity))?;
+            *last_activity = Some(activity);
+        }
+        let level = levels.iter().rev().find(|level| idle_time >= level.after);
+        // Other activity keeps the active level as well, so the backlight idle rule doesn't apply
+        // during a video. It's only asked for once input alone would reach an idle level.
+        let kept_active =
+            (level.is_some() || idle_time >= occupancy_timeout) && sources.is_active().await;
+        let level = match level {
+            Some(level) if !kept_active => level.name.clone(),
+            _ => ACTIVE_IDLE_LEVEL.to_string(),
+        };
+        self.sender.send(StateChange::IdleLevel(level))?;
+        self.sender
+            .send(StateChange::Idle(idle_time >= occupancy_timeout && !kept_active))?;
+
+        Ok(())
+    }
+
+    async fn poll(
+        &self,
+        config: &IdleModuleConfig,
+        levels: &[IdleLevelConfig],
+        occupancy_timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let sources = ActivitySources::connect(config).await;
+        let mut last_activity = None;
+        loop {
+            tokio::time::sleep(config.poll_rate).await;
+            let idle = match UserIdle::get_time() {
+                Ok(idle) => idle,
+                Err(err) => {
+                    log::warn!("Unable to read idle time: {}", err);
+                    self.set_available(false)?;
+                    continue;
+                }
+            };
+            self.set_available(true)?;
+            let idle_time = Duration::from_secs(idle.as_seconds());
+            self.report(idle_time, levels, occupancy_timeout, &mut last_activity, &sources)
+                .await?;
+        }
+    }
+
+    /// Reports the IdleHint of the logind session whenever it changes.
+    ///
+    /// While idle the idle time is advanced locally so levels are reached without waiting for another signal.
+    ///
+    /// Falls back to polling when logind is unavailable.
+    async fn watch_logind(
+        &self,
+        config: &IdleModuleConfig,
+        levels: &[IdleLevelConfig],
+        occupancy_timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let bus = config.bus;
+        let session_id = config.session.clone();
+        let session = dbus::blocking(move || {
+            let connection = dbus::connect(bus)?;
+            let session = LogindSession::new(connection, session_id.as_deref())?;
+            let hint = session.idle_hint()?;
+            Ok((session, hint))
+        })
+        .await;
+        let (session, mut hint) = match session {
+            Ok(session) => session,
+            Err(err) => {
+                log::error!("Unable to connect to logind, falling back to polling: {:?}", err);
+                return self.poll(config, levels, occupancy_timeout).await;
+            }
+        };
+        let (hint_sender, mut hint_receiver) = unbounded_channel();
+        std::thread::spawn(move || {
+            if let Err(err) = session.watch_idle_hint(hint_sender) {
+                log::error!("Unable to watch logind idle hint: {:?}", err);
+            }
+        });
+        let sources = ActivitySources::connect(config).await;
+        let mut last_activity = None;
+        loop {
+            let idle_time = hint.idle_time();
+            self.report(idle_time, levels, occupancy_timeout, &mut last_activity, &sources)
+                .await?;
+            let next_report = if hint.idle {
+                levels
+                    .iter()
+                    .map(|level| level.after)
+                    .chain(std::iter::once(occupancy_timeout))
+                    .filter(|threshold| *threshold > idle_time)
+                    .map(|threshold| threshold - idle_time)
+                    .chain(std::iter::once(config.poll_rate))
+                    .min()
+            } else {
+                None
+            };
+            tokio::select! {
+                update = hint_receiver.recv() => {
+                    match update {
+                        Some(update) => hint = update,
+                        None => {
+                            log::warn!("Lost the logind idle hint, falling back to polling");
+                            return self.poll(config, levels, occupancy_timeout).await;
+                        }
+                    }
+                }
+                _ = tokio::time::sleep(next_report.unwrap_or_default()), if next_report.is_some() => {}
+            }
+        }
+    }
 }
 
 impl Worker for IdleModule {
     fn run(&mut self, config: &Config) -> BoxFuture<anyhow::Result<()>> {
-        if let Some(config) = config.modules.idle {
+        if let Some(config) = config.modules.idle.clone() {
             async move {
+                let levels = config.levels()?;
+                let occupancy_timeout = config.occupancy_timeout()?;
+                self.set_available(true)?;
                 self.sender.send(StateChange::Idle(false))?;
 
-                loop {
-                    tokio::time::sleep(config.poll_rate).await;
-                    let idle =
-                        UserIdle::get_time().map_err(|err| anyhow::Error::msg(err.to_string()))?;
-                    if idle.as_seconds() >= config.timeout.as_secs() {
-                        self.sender.send(StateChange::Idle(true))?;
-                    } else {
-                        self.sender.send(StateChange::Idle(false))?;
+                match config.backend {
+                    IdleBackend::Polling => self.poll(&config, &levels, occupancy_timeout).await,
+                    IdleBackend::Logind => {
+                        self.watch_logind(&config, &levels, occupancy_timeout).await
                     }
                 }
             }
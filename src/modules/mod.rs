@@ -1,11 +1,18 @@
 pub use self::backlight::*;
 pub use self::custom_command::*;
 pub use self::idle::*;
+pub use self::night_light::*;
 pub use self::notifications::*;
+pub use self::screensaver::*;
 pub use self::sensors::*;
+pub use self::session_lock::*;
 
 mod backlight;
 mod custom_command;
+mod dbus;
 mod idle;
+mod night_light;
 mod notifications;
+mod screensaver;
 mod sensors;
+mod session_lock;
@@ -0,0 +1,184 @@
+use crate::config::{Config, NightLightBackend, NightLightConfig};
+use crate::core::state::PowerState;
+use crate::core::{MqttCommand, MqttMessage, StateChange, Worker};
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use serde::Deserialize;
+use tokio::process::{Child, Command};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const NIGHT_LIGHT_COMPONENT: &str = "night_light";
+
+/// Controls the colour temperature of the screen.
+pub struct NightLightModule {
+    sender: UnboundedSender<StateChange>,
+    receiver: broadcast::Receiver<MqttMessage>,
+    mqtt_sender: UnboundedSender<MqttCommand>,
+    /// Running `wlsunset` process, killed when turned off or on shutdown
+    process: Option<Child>,
+}
+
+impl NightLightModule {
+    pub fn new(
+        sender: UnboundedSender<StateChange>,
+        receiver: broadcast::Receiver<MqttMessage>,
+        mqtt_sender: UnboundedSender<MqttCommand>,
+    ) -> Self {
+        NightLightModule {
+            sender,
+            receiver,
+            mqtt_sender,
+            process: None,
+        }
+    }
+
+    pub fn state_topic(entity_id: &str) -> String {
+        format!("desktop2mqtt/{}/night_light", entity_id)
+    }
+
+    pub fn command_topic(entity_id: &str) -> String {
+        format!("{}/set", Self::state_topic(entity_id))
+    }
+
+    async fn apply(
+        &mut self,
+        config: &NightLightConfig,
+        power: bool,
+        temperature: u32,
+    ) -> anyhow::Result<()> {
+        match (config.backend, power) {
+            (NightLightBackend::Gammastep, true) => {
+                run("gammastep", &["-P", "-O", &temperature.to_string()]).await
+            }
+            (NightLightBackend::Gammastep, false) => run("gammastep", &["-x"]).await,
+            (NightLightBackend::Redshift, true) => {
+                run("redshift", &["-P", "-O", &temperature.to_string()]).await
+            }
+            (NightLightBackend::Redshift, false) => run("redshift", &["-x"]).await,
+            (NightLightBackend::Wlsunset, power) => {
+                if let Some(mut process) = self.process.take() {
+                    process.kill().await?;
+                }
+                if power {
+                    // wlsunset only transitions between a low and high temperature,
+                    // keeping both next to each other applies the temperature at any time of day
+                    let process = Command::new("wlsunset")
+                        .arg("-t")
+                        .arg(temperature.to_string())
+                        .arg("-T")
+                        .arg((temperature + 1).to_string())
+                        .kill_on_drop(true)
+                        .spawn()?;
+                    self.process = Some(process);
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn set_available(&self, available: bool) -> anyhow::Result<()> {
+        self.sender.send(StateChange::Availability {
+            component: NIGHT_LIGHT_COMPONENT.to_string(),
+            available,
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Worker for NightLightModule {
+    fn run(&mut self, config: &Config) -> BoxFuture<anyhow::Result<()>> {
+        let night_light_config = match config.modules.night_light.clone() {
+            Some(night_light_config) => night_light_config,
+            None => return futures_util::future::ok(()).boxed(),
+        };
+        let topic = Self::command_topic(&config.hass.entity_id);
+        async move {
+            let mut power = false;
+            let mut temperature = night_light_config.temperature;
+            self.set_available(true)?;
+            self.sender.send(StateChange::NightLight {
+                power,
+                color_temp: to_mireds(temperature),
+            })?;
+            self.mqtt_sender
+                .send(MqttCommand::subscribe(topic.clone()))?;
+
+            loop {
+                let msg = match self.receiver.recv().await {
+                    Ok(msg) => msg,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if msg.topic != topic {
+                    continue;
+                }
+                let update: NightLightUpdate = match msg.deserialize() {
+                    Ok(update) => update,
+                    Err(err) => {
+                        log::warn!("Invalid night light command {}: {:?}", msg.payload, err);
+                        continue;
+                    }
+                };
+                if let Some(next) = update.power {
+                    power = next.into();
+                }
+                if let Some(color_temp) = update.color_temp {
+                    temperature = from_mireds(color_temp).clamp(
+                        night_light_config.min_temperature,
+                        night_light_config.max_temperature,
+                    );
+                }
+                match self.apply(&night_light_config, power, temperature).await {
+                    Ok(()) => self.set_available(true)?,
+                    Err(err) => {
+                        log::warn!("Unable to change night light: {:?}", err);
+                        self.set_available(false)?;
+                    }
+                }
+                self.sender.send(StateChange::NightLight {
+                    power,
+                    color_temp: to_mireds(temperature),
+                })?;
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct NightLightUpdate {
+    #[serde(rename = "state")]
+    power: Option<PowerState>,
+    /// Colour temperature in mireds
+    color_temp: Option<u32>,
+}
+
+/// Converts a colour temperature between Kelvin and mireds, the conversion is its own inverse.
+pub fn to_mireds(temperature: u32) -> u32 {
+    1_000_000 / temperature.max(1)
+}
+
+fn from_mireds(color_temp: u32) -> u32 {
+    to_mireds(color_temp)
+}
+
+async fn run(program: &str, args: &[&str]) -> anyhow::Result<()> {
+    log::trace!("Running {} {:?}", program, args);
+    let output = Command::new(program).args(args).output().await?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} failed with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
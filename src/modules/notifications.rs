@@ -1,47 +1,328 @@
-use crate::core::{Worker, MqttMessage, MqttCommand};
+use crate::core::{DesktopState, Worker, MqttMessage, MqttCommand, StateChange};
+use crate::modules::dbus::{self, notifications::close_notification};
 use futures_util::future::BoxFuture;
-use crate::config::Config;
+use crate::config::{
+    get_image_dir, Config, DbusBus, NamedNotificationTimeout, NotificationHint, NotificationTimeout,
+    NotificationUrgency, NotificationsConfig,
+};
 use tokio::sync::broadcast;
-use notify_rust::Notification;
+use notify_rust::{Hint, Notification, NotificationHandle, Timeout, Urgency};
 use futures_util::FutureExt;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc::UnboundedSender;
 
+/// Action reported by notify-rust when the notification was closed instead of invoked
+const CLOSED_ACTION: &str = "__closed";
+
+/// Ids of the shown notifications by their tag, removed once the notification is gone
+type TaggedNotifications = Arc<Mutex<HashMap<String, u32>>>;
+
+/// Counter making the names of the image files of this process unique
+static IMAGE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
 pub struct NotificationsModule {
+    sender: UnboundedSender<StateChange>,
     receiver: broadcast::Receiver<MqttMessage>,
-    sender: UnboundedSender<MqttCommand>,
+    mqtt_sender: UnboundedSender<MqttCommand>,
+    /// Used once to restore do not disturb from the persisted state
+    state_updates: Option<broadcast::Receiver<DesktopState>>,
+    tagged: TaggedNotifications,
+    do_not_disturb: bool,
+    /// Notifications received while do not disturb is on
+    queue: Vec<NotificationData>,
 }
 
 impl NotificationsModule {
-    pub fn new(receiver: broadcast::Receiver<MqttMessage>, sender: UnboundedSender<MqttCommand>) -> Self {
+    pub fn new(
+        sender: UnboundedSender<StateChange>,
+        receiver: broadcast::Receiver<MqttMessage>,
+        mqtt_sender: UnboundedSender<MqttCommand>,
+        state_updates: broadcast::Receiver<DesktopState>,
+    ) -> Self {
         NotificationsModule {
-            receiver,
             sender,
+            receiver,
+            mqtt_sender,
+            state_updates: Some(state_updates),
+            tagged: Default::default(),
+            do_not_disturb: false,
+            queue: Vec::new(),
+        }
+    }
+
+    pub fn topic(entity_id: &str, config: &NotificationsConfig) -> String {
+        match config.topic.as_ref() {
+            Some(topic) => topic.clone(),
+            None => format!("desktop2mqtt/{}/notify", entity_id),
+        }
+    }
+
+    /// Topic invoked actions and closed notifications are published to.
+    pub fn response_topic(entity_id: &str, config: &NotificationsConfig) -> String {
+        format!("{}/response", Self::topic(entity_id, config))
+    }
+
+    pub fn do_not_disturb_topic(entity_id: &str) -> String {
+        format!("desktop2mqtt/{}/do_not_disturb/set", entity_id)
+    }
+
+    fn receive(
+        &mut self,
+        data: NotificationData,
+        config: &NotificationsConfig,
+        response_topic: &str,
+    ) -> anyhow::Result<()> {
+        let critical = config.urgency(data.urgency) == Some(NotificationUrgency::Critical);
+        if !self.do_not_disturb || critical {
+            return self.show(data, config, response_topic);
+        }
+        log::debug!("Queueing notification {:?} while do not disturb is on", data.title);
+        if let Some(tag) = data.tag.as_ref() {
+            self.queue.retain(|queued| queued.tag.as_ref() != Some(tag));
         }
+        self.queue.push(data);
+        self.send_state()
+    }
+
+    fn set_do_not_disturb(
+        &mut self,
+        do_not_disturb: bool,
+        config: &NotificationsConfig,
+        response_topic: &str,
+    ) -> anyhow::Result<()> {
+        self.do_not_disturb = do_not_disturb;
+        if !do_not_disturb {
+            let queue = std::mem::take(&mut self.queue);
+            let notifications = if config.do_not_disturb_summary && queue.len() > 1 {
+                vec![NotificationData::summary(&queue)]
+            } else {
+                queue
+            };
+            for data in notifications {
+                if let Err(err) = self.show(data, config, response_topic) {
+                    log::error!("Unable to show notification: {:?}", err);
+                }
+            }
+        }
+
+        self.send_state()
+    }
+
+    /// Restores do not disturb from the first published state, which is the persisted one.
+    async fn restore_do_not_disturb(&mut self) {
+        let mut state_updates = match self.state_updates.take() {
+            Some(state_updates) => state_updates,
+            None => return,
+        };
+        // Only this module changes do not disturb, so later states still hold the restored value
+        loop {
+            match state_updates.recv().await {
+                Ok(state) => {
+                    self.do_not_disturb = state.do_not_disturb.map(bool::from).unwrap_or_default();
+                    break;
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    fn send_state(&self) -> anyhow::Result<()> {
+        self.sender.send(StateChange::DoNotDisturb {
+            enabled: self.do_not_disturb,
+            queued: self.queue.len(),
+        })?;
+
+        Ok(())
+    }
+
+    fn show(
+        &self,
+        mut data: NotificationData,
+        config: &NotificationsConfig,
+        response_topic: &str,
+    ) -> anyhow::Result<()> {
+        if !data.extra.is_empty() {
+            let fields: Vec<_> = data.extra.keys().collect();
+            log::warn!("Ignoring unknown notification fields {:?}", fields);
+        }
+        let hints = data.remove_hints(config);
+        if !hints.is_empty() {
+            log::warn!("Ignoring notification hints which are not allowed {:?}", hints);
+        }
+        let mut notification = Notification::new();
+        notification.appname(data.app_name.as_ref().unwrap_or(&config.app_name));
+        // The Home Assistant notify entity only sends a message, which is shown as summary then
+        match (data.title.as_ref(), data.message.as_ref()) {
+            (Some(title), message) => {
+                notification.summary(title);
+                if let Some(message) = message {
+                    notification.body(message);
+                }
+            }
+            (None, Some(message)) => {
+                notification.summary(message);
+            }
+            (None, None) => anyhow::bail!("Notifications require a title or message"),
+        }
+        if let Some(urgency) = data.urgency.or(config.urgency) {
+            notification.urgency(urgency.into());
+        }
+        if let Some(timeout) = data.timeout.or(config.timeout) {
+            notification.timeout(timeout);
+        }
+        if let Some(icon) = data.icon.as_ref().or(config.icon.as_ref()) {
+            notification.icon(icon);
+        }
+        let mut image_file = None;
+        if let Some(image) = data.image.as_ref() {
+            if is_image_path(image) {
+                notification.image_path(image);
+            } else {
+                let file = NotificationImage::write(image)?;
+                notification.image_path(&file.path.to_string_lossy());
+                image_file = Some(file);
+            }
+        }
+        if let Some(sound_name) = data.sound_name.as_ref() {
+            notification.sound_name(sound_name);
+        }
+        if let Some(category) = data.category.as_ref() {
+            notification.hint(Hint::Category(category.clone()));
+        }
+        for action in &data.actions {
+            notification.action(action.id(), action.label());
+        }
+        let replaces = data
+            .tag
+            .as_ref()
+            .and_then(|tag| self.tagged.lock().unwrap().get(tag).copied());
+        if let Some(id) = replaces {
+            log::debug!("Replacing notification {}", id);
+            notification.id(id);
+        }
+        let handle = notification.show()?;
+        if let Some(tag) = data.tag.as_ref() {
+            self.tagged.lock().unwrap().insert(tag.clone(), handle.id());
+        }
+        // The replaced notification keeps its id, so its waiting task reports the responses
+        let respond = replaces.is_none() && (!data.actions.is_empty() || data.tag.is_some());
+        if !respond && image_file.is_none() {
+            return Ok(());
+        }
+        let sender = self.mqtt_sender.clone();
+        let tagged = self.tagged.clone();
+        let response_topic = response_topic.to_string();
+        // Waiting for the action blocks until the notification is gone, which may be never,
+        // so it gets its own thread instead of holding one of the blocking pool of tokio
+        std::thread::spawn(move || {
+            if respond {
+                wait_for_response(handle, data.tag, response_topic, sender, tagged);
+            } else {
+                handle.wait_for_action(|_| {});
+            }
+            // The image is needed for as long as the notification is shown
+            drop(image_file);
+        });
+
+        Ok(())
+    }
+
+    async fn close(&mut self, tag: &str) -> anyhow::Result<()> {
+        let queued = self.queue.len();
+        self.queue.retain(|queued| queued.tag.as_deref() != Some(tag));
+        if self.queue.len() != queued {
+            self.send_state()?;
+        }
+        let id = match self.tagged.lock().unwrap().get(tag).copied() {
+            Some(id) => id,
+            None => {
+                if self.queue.len() == queued {
+                    log::warn!("No notification with tag {} to close", tag);
+                }
+                return Ok(());
+            }
+        };
+        dbus::blocking(move || {
+            let connection = dbus::connect(DbusBus::Session)?;
+            close_notification(&connection, id)
+        })
+        .await
     }
 }
 
 impl Worker for NotificationsModule {
     fn run(&mut self, config: &Config) -> BoxFuture<anyhow::Result<()>> {
-        let topic = format!("desktop2mqtt/{}/notify", config.hass.entity_id);
-        if config.modules.notifications.is_none() {
-            return futures_util::future::ok(()).boxed();
-        }
+        let notifications_config = match config.modules.notifications.clone() {
+            Some(notifications_config) => notifications_config,
+            None => return futures_util::future::ok(()).boxed(),
+        };
+        let topic = Self::topic(&config.hass.entity_id, &notifications_config);
+        let response_topic = Self::response_topic(&config.hass.entity_id, &notifications_config);
+        let do_not_disturb_topic = Self::do_not_disturb_topic(&config.hass.entity_id);
         async move {
-            self.sender.send(MqttCommand::Subscribe(topic.clone()))?;
-            while let Ok(msg) = self.receiver.recv().await {
+            self.restore_do_not_disturb().await;
+            self.send_state()?;
+            self.mqtt_sender.send(MqttCommand::Subscribe(topic.clone()))?;
+            self.mqtt_sender
+                .send(MqttCommand::Subscribe(do_not_disturb_topic.clone()))?;
+            loop {
+                let msg = match self.receiver.recv().await {
+                    Ok(msg) => msg,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if msg.topic == do_not_disturb_topic {
+                    let do_not_disturb = match msg.payload.as_str() {
+                        "ON" => true,
+                        "OFF" => false,
+                        payload => {
+                            log::warn!("Unknown do not disturb command {}", payload);
+                            continue;
+                        }
+                    };
+                    self.set_do_not_disturb(
+                        do_not_disturb,
+                        &notifications_config,
+                        &response_topic,
+                    )?;
+                    continue;
+                }
                 if msg.topic != topic {
                     continue;
                 }
-                let data: NotificationData = msg.deserialize()?;
-                let mut notification = Notification::new();
-                notification
-                    .summary(&data.title)
-                    .appname("desktop2mqtt");
-                if let Some(message) = data.message {
-                    notification.body(&message);
+                let data: NotificationData = match msg.deserialize() {
+                    Ok(data) => data,
+                    Err(err) => {
+                        log::warn!("Invalid notification {}: {:?}", msg.payload, err);
+                        continue;
+                    }
+                };
+                if data.close {
+                    let tag = match data.tag {
+                        Some(tag) => tag,
+                        None => {
+                            log::warn!("Closing a notification requires its tag");
+                            continue;
+                        }
+                    };
+                    if let Err(err) = self.close(&tag).await {
+                        log::error!("Unable to close notification: {:?}", err);
+                    }
+                } else {
+                    let result = self.receive(data, &notifications_config, &response_topic);
+                    if let Err(err) = result {
+                        log::error!("Unable to show notification: {:?}", err);
+                    }
                 }
-                notification.show()?;
             }
 
             Ok(())
@@ -49,9 +330,229 @@ impl Worker for NotificationsModule {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+fn is_image_path(image: &str) -> bool {
+    image.starts_with('/') || image.starts_with("file://")
+}
+
+/// Base64 encoded image written to a file of its own, removed again when dropped.
+struct NotificationImage {
+    path: PathBuf,
+}
+
+impl NotificationImage {
+    fn write(image: &str) -> anyhow::Result<Self> {
+        // Accept data urls as well as plain base64
+        let data = match image.split_once(";base64,") {
+            Some((_, data)) => data,
+            None => image,
+        };
+        let data = base64::decode(data.trim())?;
+        let dir = get_image_dir()
+            .ok_or_else(|| anyhow::anyhow!("No directory for notification images found"))?;
+        DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+        loop {
+            let index = IMAGE_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("{}-{}", std::process::id(), index));
+            // Never reuses or follows an existing file, e.g. left behind by a crashed run
+            let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            };
+            log::trace!("Writing notification image to {:?}", path);
+            let image = NotificationImage { path };
+            file.write_all(&data)?;
+
+            return Ok(image);
+        }
+    }
+}
+
+impl Drop for NotificationImage {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            log::warn!("Unable to remove notification image {:?}: {:?}", self.path, err);
+        }
+    }
+}
+
+fn wait_for_response(
+    handle: NotificationHandle,
+    tag: Option<String>,
+    topic: String,
+    sender: UnboundedSender<MqttCommand>,
+    tagged: TaggedNotifications,
+) {
+    let id = handle.id();
+    handle.wait_for_action(|action| {
+        if let Some(tag) = tag.as_ref() {
+            let mut tagged = tagged.lock().unwrap();
+            // A new notification may have been shown for the tag in the meantime
+            if tagged.get(tag) == Some(&id) {
+                tagged.remove(tag);
+            }
+        }
+        let response = match action {
+            CLOSED_ACTION => NotificationResponse {
+                event_type: NotificationEvent::Closed,
+                tag,
+                action: None,
+            },
+            action => NotificationResponse {
+                event_type: NotificationEvent::Action,
+                tag,
+                action: Some(action.to_string()),
+            },
+        };
+        log::debug!("Notification response {:?}", response);
+        let result = MqttCommand::event_json(topic, &response)
+            .and_then(|command| Ok(sender.send(command)?));
+        if let Err(err) = result {
+            log::error!("Unable to publish notification response: {:?}", err);
+        }
+    });
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
 struct NotificationData {
     #[serde(default)]
     message: Option<String>,
-    title: String,
+    /// Summary of the notification, falls back to the message
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    actions: Vec<NotificationAction>,
+    /// Returned with every response, a new notification with the same tag replaces this one
+    #[serde(default)]
+    tag: Option<String>,
+    /// Closes the notification with the given tag instead of showing one
+    #[serde(default)]
+    close: bool,
+    #[serde(default)]
+    urgency: Option<NotificationUrgency>,
+    #[serde(default)]
+    timeout: Option<NotificationTimeout>,
+    /// Icon name or path
+    #[serde(default)]
+    icon: Option<String>,
+    /// Image path or base64 encoded image data
+    #[serde(default)]
+    image: Option<String>,
+    #[serde(default)]
+    sound_name: Option<String>,
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    app_name: Option<String>,
+    /// Fields not known to this version, logged instead of rejecting the notification
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
+}
+
+impl NotificationData {
+    /// Single notification listing the titles of the given notifications.
+    fn summary(notifications: &[NotificationData]) -> Self {
+        let titles: Vec<_> = notifications
+            .iter()
+            .filter_map(|data| data.title.clone().or_else(|| data.message.clone()))
+            .collect();
+        NotificationData {
+            title: Some(format!(
+                "{} notifications while do not disturb was on",
+                notifications.len()
+            )),
+            message: Some(titles.join("\n")),
+            ..Default::default()
+        }
+    }
+
+    /// Removes the hints which are not allowed by the config and returns them.
+    fn remove_hints(&mut self, config: &NotificationsConfig) -> Vec<NotificationHint> {
+        let mut removed = Vec::new();
+        if !config.is_hint_allowed(NotificationHint::Urgency) && self.urgency.take().is_some() {
+            removed.push(NotificationHint::Urgency);
+        }
+        if !config.is_hint_allowed(NotificationHint::Category) && self.category.take().is_some() {
+            removed.push(NotificationHint::Category);
+        }
+        if !config.is_hint_allowed(NotificationHint::SoundName) && self.sound_name.take().is_some()
+        {
+            removed.push(NotificationHint::SoundName);
+        }
+        if !config.is_hint_allowed(NotificationHint::Image) && self.image.take().is_some() {
+            removed.push(NotificationHint::Image);
+        }
+
+        removed
+    }
+}
+
+impl From<NotificationUrgency> for Urgency {
+    fn from(urgency: NotificationUrgency) -> Self {
+        match urgency {
+            NotificationUrgency::Low => Urgency::Low,
+            NotificationUrgency::Normal => Urgency::Normal,
+            NotificationUrgency::Critical => Urgency::Critical,
+        }
+    }
+}
+
+impl From<NotificationTimeout> for Timeout {
+    fn from(timeout: NotificationTimeout) -> Self {
+        match timeout {
+            NotificationTimeout::Milliseconds(milliseconds) => Timeout::Milliseconds(milliseconds),
+            NotificationTimeout::Named(NamedNotificationTimeout::Never) => Timeout::Never,
+            NotificationTimeout::Named(NamedNotificationTimeout::Default) => Timeout::Default,
+        }
+    }
+}
+
+/// Button shown on the notification, either a label doubling as action id or both separately
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum NotificationAction {
+    Label(String),
+    Action { id: String, label: String },
+}
+
+impl NotificationAction {
+    fn id(&self) -> &str {
+        match self {
+            Self::Label(label) => label,
+            Self::Action { id, .. } => id,
+        }
+    }
+
+    fn label(&self) -> &str {
+        match self {
+            Self::Label(label) => label,
+            Self::Action { label, .. } => label,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationEvent {
+    Action,
+    Closed,
+}
+
+impl NotificationEvent {
+    pub const ALL: [NotificationEvent; 2] = [NotificationEvent::Action, NotificationEvent::Closed];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Action => "action",
+            Self::Closed => "closed",
+        }
+    }
+}
+
+/// Published to the response topic, `event_type` makes it usable by Home Assistant event entities.
+#[derive(Debug, Clone, Serialize)]
+struct NotificationResponse {
+    event_type: NotificationEvent,
+    tag: Option<String>,
+    action: Option<String>,
 }
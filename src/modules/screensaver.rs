@@ -0,0 +1,119 @@
+use crate::config::Config;
+use crate::core::{MqttCommand, MqttMessage, StateChange, Worker};
+use crate::modules::dbus::{self, screensaver::ScreenSaverInhibitor};
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const SCREENSAVER_INHIBIT_COMPONENT: &str = "screensaver_inhibit";
+
+/// Keeps the screen saver from activating while switched on.
+///
+/// The inhibit is released when the module is dropped on shutdown.
+pub struct ScreenSaverInhibitModule {
+    sender: UnboundedSender<StateChange>,
+    receiver: broadcast::Receiver<MqttMessage>,
+    mqtt_sender: UnboundedSender<MqttCommand>,
+    inhibitor: Option<Arc<Mutex<ScreenSaverInhibitor>>>,
+}
+
+impl ScreenSaverInhibitModule {
+    pub fn new(
+        sender: UnboundedSender<StateChange>,
+        receiver: broadcast::Receiver<MqttMessage>,
+        mqtt_sender: UnboundedSender<MqttCommand>,
+    ) -> Self {
+        ScreenSaverInhibitModule {
+            sender,
+            receiver,
+            mqtt_sender,
+            inhibitor: None,
+        }
+    }
+
+    pub fn command_topic(entity_id: &str) -> String {
+        format!("desktop2mqtt/{}/screensaver_inhibit/set", entity_id)
+    }
+
+    fn set_available(&self, available: bool) -> anyhow::Result<()> {
+        self.sender.send(StateChange::Availability {
+            component: SCREENSAVER_INHIBIT_COMPONENT.to_string(),
+            available,
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Worker for ScreenSaverInhibitModule {
+    fn run(&mut self, config: &Config) -> BoxFuture<anyhow::Result<()>> {
+        let inhibit_config = match config.modules.screensaver_inhibit.clone() {
+            Some(inhibit_config) => inhibit_config,
+            None => return futures_util::future::ok(()).boxed(),
+        };
+        let topic = Self::command_topic(&config.hass.entity_id);
+        async move {
+            let bus = inhibit_config.bus;
+            let inhibitor = match dbus::blocking(move || dbus::connect(bus)).await {
+                Ok(connection) => Arc::new(Mutex::new(ScreenSaverInhibitor::new(connection))),
+                Err(err) => {
+                    log::error!("Unable to connect to the screen saver: {:?}", err);
+                    self.set_available(false)?;
+                    return Ok(());
+                }
+            };
+            self.inhibitor = Some(inhibitor.clone());
+            self.set_available(true)?;
+            self.sender.send(StateChange::ScreenSaverInhibit(false))?;
+            self.mqtt_sender
+                .send(MqttCommand::subscribe(topic.clone()))?;
+
+            loop {
+                let msg = match self.receiver.recv().await {
+                    Ok(msg) => msg,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if msg.topic != topic {
+                    continue;
+                }
+                let inhibit = match msg.payload.as_str() {
+                    "ON" => true,
+                    "OFF" => false,
+                    payload => {
+                        log::warn!("Unknown screen saver inhibit command {}", payload);
+                        continue;
+                    }
+                };
+                let reason = inhibit_config.reason.clone();
+                let target = inhibitor.clone();
+                let result = dbus::blocking(move || {
+                    let mut inhibitor = target.lock().unwrap();
+                    if inhibit {
+                        inhibitor.inhibit(&reason)?;
+                    } else {
+                        inhibitor.release()?;
+                    }
+                    Ok(inhibitor.is_active())
+                })
+                .await;
+                match result {
+                    Ok(active) => {
+                        self.set_available(true)?;
+                        self.sender.send(StateChange::ScreenSaverInhibit(active))?;
+                    }
+                    Err(err) => {
+                        log::warn!("Unable to change screen saver inhibit: {:?}", err);
+                        self.set_available(false)?;
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
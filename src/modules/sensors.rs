@@ -37,8 +37,19 @@ impl Worker for SensorsModule {
                 let handle = tokio::runtime::Handle::try_current()?;
                 loop {
                     for sensor_type in &config.types {
+                        let meta = sensor_type.get_meta();
                         let sensor_type = sensor_type.clone();
-                        let values = handle.spawn_blocking(move || sensor_type.get_values()).await??;
+                        let values = match handle.spawn_blocking(move || sensor_type.get_values()).await? {
+                            Ok(values) => values,
+                            Err(err) => {
+                                log::warn!("Unable to read sensor values: {:?}", err);
+                                Vec::new()
+                            }
+                        };
+                        for sensor in meta {
+                            let available = values.iter().any(|(name, _)| name == &sensor.id);
+                            self.sender.send(StateChange::Availability { component: sensor.id, available })?;
+                        }
                         for (name, value) in values {
                             self.sender.send(StateChange::Sensor { name, value: (value * 100.0).round() / 100.0  })?;
                         }
@@ -153,15 +164,18 @@ impl SensorTypeExt for SensorType {
             SensorType::DiskUsage { disks } => {
                 let mut mounts = Vec::new();
                 for disk in disks {
-                    mounts.push(platform.mount_at(disk)?);
+                    match platform.mount_at(disk) {
+                        Ok(fs) => mounts.push((disk, fs)),
+                        Err(err) => log::warn!("Unable to read disk usage of {}: {}", disk, err),
+                    }
                 }
                 log::trace!("{:#?}", mounts);
 
                 let sensors = mounts.into_iter()
-                    .map(|fs| {
+                    .map(|(disk, fs)| {
                         let usage = fs.free.as_u64() as f64 / fs.total.as_u64() as f64;
                         let usage = ((1f64 - usage) * 100.0) as f32;
-                        (get_disk_usage_id(&fs.fs_mounted_on), usage)
+                        (get_disk_usage_id(disk), usage)
                     })
                     .collect();
                 Ok(sensors)
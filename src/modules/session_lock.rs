@@ -0,0 +1,129 @@
+use crate::config::Config;
+use crate::core::{MqttCommand, MqttMessage, StateChange, Worker};
+use crate::modules::dbus::{self, logind::LogindSession};
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const SESSION_LOCK_COMPONENT: &str = "session_lock";
+pub const LOCK_PAYLOAD: &str = "LOCK";
+pub const UNLOCK_PAYLOAD: &str = "UNLOCK";
+
+/// Reports the lock state of the logind session and allows locking and unlocking it.
+pub struct SessionLockModule {
+    sender: UnboundedSender<StateChange>,
+    receiver: broadcast::Receiver<MqttMessage>,
+    mqtt_sender: UnboundedSender<MqttCommand>,
+}
+
+impl SessionLockModule {
+    pub fn new(
+        sender: UnboundedSender<StateChange>,
+        receiver: broadcast::Receiver<MqttMessage>,
+        mqtt_sender: UnboundedSender<MqttCommand>,
+    ) -> Self {
+        SessionLockModule {
+            sender,
+            receiver,
+            mqtt_sender,
+        }
+    }
+
+    pub fn command_topic(entity_id: &str) -> String {
+        format!("desktop2mqtt/{}/session_lock/set", entity_id)
+    }
+
+    async fn report(&self, session: &LogindSession, last: &mut Option<bool>) -> anyhow::Result<()> {
+        let session = session.clone();
+        match dbus::blocking(move || session.is_locked()).await {
+            Ok(locked) => {
+                self.set_available(true)?;
+                if *last != Some(locked) {
+                    self.sender.send(StateChange::SessionLock(locked))?;
+                    *last = Some(locked);
+                }
+            }
+            Err(err) => {
+                log::warn!("Unable to read session lock state: {:?}", err);
+                self.set_available(false)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_available(&self, available: bool) -> anyhow::Result<()> {
+        self.sender.send(StateChange::Availability {
+            component: SESSION_LOCK_COMPONENT.to_string(),
+            available,
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Worker for SessionLockModule {
+    fn run(&mut self, config: &Config) -> BoxFuture<anyhow::Result<()>> {
+        let lock_config = match config.modules.session_lock.clone() {
+            Some(lock_config) => lock_config,
+            None => return futures_util::future::ok(()).boxed(),
+        };
+        let topic = Self::command_topic(&config.hass.entity_id);
+        async move {
+            let bus = lock_config.bus;
+            let session_id = lock_config.session.clone();
+            let session = dbus::blocking(move || {
+                let connection = dbus::connect(bus)?;
+                LogindSession::new(connection, session_id.as_deref())
+            })
+            .await;
+            let session = match session {
+                Ok(session) => session,
+                Err(err) => {
+                    log::error!("Unable to connect to logind: {:?}", err);
+                    self.set_available(false)?;
+                    return Ok(());
+                }
+            };
+            self.mqtt_sender
+                .send(MqttCommand::subscribe(topic.clone()))?;
+            let mut poll_interval = tokio::time::interval(lock_config.poll_rate);
+            let mut locked = None;
+            loop {
+                tokio::select! {
+                    _ = poll_interval.tick() => {
+                        self.report(&session, &mut locked).await?;
+                    }
+                    msg = self.receiver.recv() => {
+                        let msg = match msg {
+                            Ok(msg) => msg,
+                            Err(RecvError::Lagged(_)) => continue,
+                            Err(RecvError::Closed) => break,
+                        };
+                        if msg.topic != topic {
+                            continue;
+                        }
+                        let target = session.clone();
+                        let result = match msg.payload.as_str() {
+                            LOCK_PAYLOAD => dbus::blocking(move || target.lock()).await,
+                            UNLOCK_PAYLOAD => dbus::blocking(move || target.unlock()).await,
+                            payload => {
+                                log::warn!("Unknown session lock command {}", payload);
+                                continue;
+                            }
+                        };
+                        if let Err(err) = result {
+                            log::warn!("Unable to change session lock state: {:?}", err);
+                        }
+                        self.report(&session, &mut locked).await?;
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}